@@ -11,6 +11,12 @@ const HEADLESS_ARGS: &[&str] = &[
     "isa-debug-exit,iobase=0xf4,iosize=0x04",
     "-display",
     "none",
+    // pin the clock, so tests know what date to expect
+    "-rtc",
+    "base=2021-06-15T12:34:56",
+    // enough cpus for the smp tests to have something to start
+    "-smp",
+    "4",
 ];
 const TEST_TIMEOUT: Duration = Duration::from_secs(10);
 
@@ -0,0 +1,223 @@
+use crate::memory;
+use alloc::vec::Vec;
+use core::{mem, ptr, slice};
+use x86_64::PhysAddr;
+
+static RSDP: spin::Once<PhysAddr> = spin::Once::new();
+static MADT: spin::Once<Madt> = spin::Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The bootloader didn't find an RSDP.
+    NoRsdp,
+    InvalidRsdp,
+    /// The table with this signature failed its checksum.
+    InvalidChecksum([u8; 4]),
+    /// There's no table with this signature.
+    MissingTable([u8; 4]),
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // the rest only exists from revision 2 on
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+/// The interrupt controllers and processors described by the MADT.
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic: PhysAddr,
+    /// Whether the legacy 8259 PICs are installed too, and need disabling.
+    pub legacy_pics: bool,
+    /// Only the processors that are enabled or can be brought online.
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    /// Whether the processor is ready to be started. If not, it can only be brought online later
+    /// on, with the firmware's help.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// The first global system interrupt this I/O APIC handles.
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ that isn't identity mapped onto a global system interrupt, or doesn't use the
+/// usual edge triggered, active high signalling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+impl InterruptOverride {
+    pub fn active_low(&self) -> bool {
+        self.flags & 0b11 == 0b11
+    }
+
+    pub fn level_triggered(&self) -> bool {
+        (self.flags >> 2) & 0b11 == 0b11
+    }
+}
+
+/// Finds and parses the MADT, starting from the RSDP the bootloader found. The result is kept,
+/// so later calls and `madt` don't parse it again.
+pub fn init(rsdp_address: Option<u64>) -> Result<&'static Madt, AcpiError> {
+    if let Some(madt) = MADT.get() {
+        return Ok(madt);
+    }
+
+    let rsdp_address = PhysAddr::new(rsdp_address.ok_or(AcpiError::NoRsdp)?);
+    RSDP.call_once(|| rsdp_address);
+    let madt = unsafe { find_table(rsdp_address, *b"APIC") }?;
+    let madt = unsafe { parse_madt(madt) };
+    Ok(MADT.call_once(|| madt))
+}
+
+/// The MADT, if `init` found one.
+pub fn madt() -> Option<&'static Madt> {
+    MADT.get()
+}
+
+/// The physical address of the HPET's registers, if `init` was given an RSDP and there is one.
+pub fn hpet_address() -> Option<PhysAddr> {
+    let table = unsafe { find_table(*RSDP.get()?, *b"HPET") }.ok()?;
+    // the address is the last field of a generic address structure after the header and id
+    let address: u64 = unsafe { read(table + 44u64) };
+    Some(PhysAddr::new(address))
+}
+
+unsafe fn read<T: Copy>(physical: PhysAddr) -> T {
+    ptr::read_unaligned(memory::physical_to_virtual(physical).as_ptr())
+}
+
+unsafe fn checksum_ok(physical: PhysAddr, len: usize) -> bool {
+    let bytes = slice::from_raw_parts(memory::physical_to_virtual(physical).as_ptr::<u8>(), len);
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+// returns the address of the table with the given signature, after checking its checksum
+unsafe fn find_table(rsdp_address: PhysAddr, signature: [u8; 4]) -> Result<PhysAddr, AcpiError> {
+    let rsdp: Rsdp = read(rsdp_address);
+    if &rsdp.signature != b"RSD PTR " || !checksum_ok(rsdp_address, 20) {
+        return Err(AcpiError::InvalidRsdp);
+    }
+
+    // the XSDT has 64 bit pointers to the other tables, the RSDT 32 bit ones
+    let (root, entry_size) = match rsdp.revision {
+        0 | 1 => (PhysAddr::new(rsdp.rsdt_address as u64), 4),
+        _ => (PhysAddr::new(rsdp.xsdt_address), 8),
+    };
+    let header = checked_header(root)?;
+
+    let entries = (header.length as usize - mem::size_of::<SdtHeader>()) / entry_size;
+    for entry in 0..entries {
+        let entry_address = root + mem::size_of::<SdtHeader>() + entry * entry_size;
+        let table = PhysAddr::new(match entry_size {
+            4 => read::<u32>(entry_address) as u64,
+            _ => read::<u64>(entry_address),
+        });
+
+        if read::<SdtHeader>(table).signature == signature {
+            checked_header(table)?;
+            return Ok(table);
+        }
+    }
+
+    Err(AcpiError::MissingTable(signature))
+}
+
+unsafe fn checked_header(table: PhysAddr) -> Result<SdtHeader, AcpiError> {
+    let header: SdtHeader = read(table);
+    match checksum_ok(table, header.length as usize) {
+        true => Ok(header),
+        false => Err(AcpiError::InvalidChecksum(header.signature)),
+    }
+}
+
+unsafe fn parse_madt(table: PhysAddr) -> Madt {
+    let header: SdtHeader = read(table);
+    let fields = table + mem::size_of::<SdtHeader>();
+    let mut madt = Madt {
+        local_apic: PhysAddr::new(read::<u32>(fields) as u64),
+        legacy_pics: read::<u32>(fields + 4u64) & 1 != 0,
+        processors: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    // the rest of the table is a list of variable length entries
+    let end = table + header.length as u64;
+    let mut entry = fields + 8u64;
+    while entry + 2u64 <= end {
+        let kind: u8 = read(entry);
+        let len: u8 = read(entry + 1u64);
+        if len < 2 {
+            break;
+        }
+
+        match kind {
+            0 => {
+                let flags: u32 = read(entry + 4u64);
+                // bit 0 is enabled, bit 1 is online capable
+                if flags & 0b11 != 0 {
+                    madt.processors.push(Processor {
+                        processor_id: read(entry + 2u64),
+                        apic_id: read(entry + 3u64),
+                        enabled: flags & 1 != 0,
+                    });
+                }
+            }
+            1 => madt.io_apics.push(IoApicInfo {
+                id: read(entry + 2u64),
+                address: PhysAddr::new(read::<u32>(entry + 4u64) as u64),
+                gsi_base: read(entry + 8u64),
+            }),
+            2 => madt.overrides.push(InterruptOverride {
+                irq: read(entry + 3u64),
+                gsi: read(entry + 4u64),
+                flags: read(entry + 8u64),
+            }),
+            5 => madt.local_apic = PhysAddr::new(read(entry + 4u64)),
+            _ => {}
+        }
+
+        entry += len as u64;
+    }
+
+    madt
+}
@@ -1,54 +1,326 @@
-use core::alloc::Layout;
-use linked_list_allocator::LockedHeap;
+mod guard;
+mod slab;
+mod tracker;
+
+pub use guard::POISON;
+pub use tracker::TrackedAllocation;
+
+use crate::vmm::{Region, VirtualMemoryManager, VMM};
+use alloc::vec::Vec;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use linked_list_allocator::Heap;
+use slab::SlabCache;
+use tracker::AllocationTracker;
 use x86_64::{
-    structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
-    },
+    align_up,
+    structures::paging::{mapper::MapToError, PageSize, PageTableFlags, Size4KiB},
     VirtAddr,
 };
 
 pub const HEAP_START: usize = 0x4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB, mapped up front
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB, the heap never grows past this
+const HEAP_GROWTH: usize = 64 * 1024; // grow by at least this much at a time
+
+/// Block sizes of the slab caches. Anything bigger comes straight from the fallback heap.
+pub const SLAB_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelAllocator = KernelAllocator::empty();
+
+// whether new allocations are tagged with their caller, see `track_leaks`
+static TRACKING: AtomicBool = AtomicBool::new(false);
+
+/// A snapshot of the heap's usage.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub bytes_allocated: usize,
+    pub bytes_free: usize,
+    pub peak_bytes_allocated: usize,
+    pub allocations: usize,
+    /// The biggest allocation that can be made without growing the heap.
+    pub largest_free_block: usize,
+}
+
+/// Serves small allocations from fixed-size slab caches, and everything else from a linked list
+/// heap that maps more pages onto its end whenever it runs out of space.
+pub struct KernelAllocator {
+    heap: spin::Mutex<KernelHeap>,
+}
+
+struct KernelHeap {
+    slabs: [SlabCache; SLAB_SIZES.len()],
+    fallback: Heap,
+    bytes_allocated: usize,
+    peak_bytes_allocated: usize,
+    allocations: usize,
+    tracker: AllocationTracker,
+}
+
+impl KernelAllocator {
+    pub const fn empty() -> Self {
+        Self {
+            heap: spin::Mutex::new(KernelHeap {
+                slabs: [
+                    SlabCache::new(SLAB_SIZES[0]),
+                    SlabCache::new(SLAB_SIZES[1]),
+                    SlabCache::new(SLAB_SIZES[2]),
+                    SlabCache::new(SLAB_SIZES[3]),
+                    SlabCache::new(SLAB_SIZES[4]),
+                    SlabCache::new(SLAB_SIZES[5]),
+                    SlabCache::new(SLAB_SIZES[6]),
+                    SlabCache::new(SLAB_SIZES[7]),
+                    SlabCache::new(SLAB_SIZES[8]),
+                ],
+                fallback: Heap::empty(),
+                bytes_allocated: 0,
+                peak_bytes_allocated: 0,
+                allocations: 0,
+                tracker: AllocationTracker::new(),
+            }),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let caller = match TRACKING.load(Ordering::Relaxed) {
+            true => Some(tracker::caller_address()),
+            false => None,
+        };
+        let block_layout = match guard::block_layout(layout) {
+            Some(block_layout) => block_layout,
+            None => return ptr::null_mut(),
+        };
+
+        let mut heap = self.heap.lock();
+        let block = heap.allocate(block_layout);
+        if block.is_null() {
+            return block;
+        }
+
+        let ptr = guard::add_red_zones(block, layout);
+        heap.record_allocation(ptr, layout, caller);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // check the red zones before locking, so a corruption panic doesn't leave the heap locked
+        let block = guard::check_and_poison(ptr, layout);
+
+        let mut heap = self.heap.lock();
+        heap.record_deallocation(ptr, layout);
+        heap.deallocate(block, guard::block_layout(layout).unwrap())
+    }
+}
+
+impl KernelHeap {
+    fn record_allocation(&mut self, ptr: *mut u8, layout: Layout, caller: Option<usize>) {
+        self.bytes_allocated += layout.size();
+        self.peak_bytes_allocated = self.peak_bytes_allocated.max(self.bytes_allocated);
+        self.allocations += 1;
+
+        if let Some(caller) = caller {
+            self.tracker.track(TrackedAllocation {
+                address: ptr as usize,
+                size: layout.size(),
+                caller,
+            });
+        }
+    }
+
+    fn record_deallocation(&mut self, ptr: *mut u8, layout: Layout) {
+        self.bytes_allocated -= layout.size();
+        self.allocations -= 1;
+        self.tracker.forget(ptr as usize);
+    }
+
+    fn stats(&mut self) -> HeapStats {
+        let slab_free_bytes: usize = self.slabs.iter().map(SlabCache::free_bytes).sum();
+        let largest_slab_block = self
+            .slabs
+            .iter()
+            .filter(|slab| slab.free_bytes() > 0)
+            .map(SlabCache::block_size)
+            .max()
+            .unwrap_or(0);
+
+        HeapStats {
+            bytes_allocated: self.bytes_allocated,
+            bytes_free: self.fallback.free() + slab_free_bytes,
+            peak_bytes_allocated: self.peak_bytes_allocated,
+            allocations: self.allocations,
+            largest_free_block: self.largest_fallback_block().max(largest_slab_block),
+        }
+    }
+
+    // the linked list heap doesn't expose its holes, so search for the biggest allocation that
+    // succeeds instead. freeing each probe merges the hole straight back together.
+    fn largest_fallback_block(&mut self) -> usize {
+        let (mut low, mut high) = (0, self.fallback.free());
+        while low < high {
+            let size = (low + high + 1) / 2;
+            let layout = Layout::from_size_align(size, 1).unwrap();
+            match self.fallback.allocate_first_fit(layout) {
+                Ok(probe) => {
+                    unsafe { self.fallback.deallocate(probe, layout) };
+                    low = size;
+                }
+                Err(()) => high = size - 1,
+            }
+        }
+        low
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let index = match slab_index(&layout) {
+            Some(index) => index,
+            None => return self.allocate_fallback(layout),
+        };
+
+        if let Some(block) = self.slabs[index].pop() {
+            return block;
+        }
+
+        // the cache is empty, so refill it with a fresh slab from the fallback heap
+        let slab = &self.slabs[index];
+        let slab_layout = Layout::from_size_align(slab.slab_size(), slab.block_size()).unwrap();
+        let new_slab = self.allocate_fallback(slab_layout);
+        if new_slab.is_null() {
+            return new_slab;
+        }
+
+        unsafe {
+            self.slabs[index].add_slab(new_slab);
+        }
+        self.slabs[index].pop().unwrap()
+    }
+
+    fn allocate_fallback(&mut self, layout: Layout) -> *mut u8 {
+        loop {
+            if let Ok(allocation) = self.fallback.allocate_first_fit(layout) {
+                return allocation.as_ptr();
+            }
+
+            // out of space, so map some more pages onto the end and try again
+            if grow_heap(&mut self.fallback, layout).is_err() {
+                return ptr::null_mut();
+            }
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match slab_index(&layout) {
+            Some(index) => self.slabs[index].push(ptr),
+            None => self
+                .fallback
+                .deallocate(NonNull::new_unchecked(ptr), layout),
+        }
+    }
+}
+
+// find the smallest slab cache whose blocks fit the layout's size and alignment
+fn slab_index(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    SLAB_SIZES.iter().position(|&block_size| block_size >= size)
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "heap: {} bytes in {} allocations (peak {}), {} bytes free (largest block {})",
+            self.bytes_allocated,
+            self.allocations,
+            self.peak_bytes_allocated,
+            self.bytes_free,
+            self.largest_free_block
+        )
+    }
+}
+
+pub fn stats() -> HeapStats {
+    ALLOCATOR.heap.lock().stats()
+}
+
+/// Like `stats`, but gives up instead of spinning if the heap is locked.
+pub fn try_stats() -> Option<HeapStats> {
+    ALLOCATOR.heap.try_lock().map(|mut heap| heap.stats())
+}
+
+/// Runs the given function with every allocation tagged by its caller, and returns the ones it
+/// left behind. Other code allocating at the same time (e.g. interrupt handlers) is included.
+pub fn track_leaks(f: impl FnOnce()) -> Vec<TrackedAllocation> {
+    ALLOCATOR.heap.lock().tracker.clear();
+    TRACKING.store(true, Ordering::SeqCst);
+    f();
+    TRACKING.store(false, Ordering::SeqCst);
+
+    // tracking is off, so the table can only shrink while we make room to copy it out
+    let len = ALLOCATOR.heap.lock().tracker.live().len();
+    let mut leaks = Vec::with_capacity(len);
+
+    let mut heap = ALLOCATOR.heap.lock();
+    let overflowed = heap.tracker.overflowed();
+    leaks.extend_from_slice(heap.tracker.live());
+    // leftover entries would make every later deallocation search through them
+    heap.tracker.clear();
+    drop(heap);
+
+    assert!(!overflowed, "too many live allocations to track leaks");
+    leaks
+}
 
 #[alloc_error_handler]
 fn alloc_error_handler(layout: Layout) -> ! {
     panic!(
-        "Allocation error: {:?}\nConsider increasing the heap size in allocator.rs",
-        layout
+        "Allocation error: {:?}\nThe heap could not grow any further (limit is {} bytes)\n{}",
+        layout,
+        HEAP_MAX_SIZE,
+        stats()
     )
 }
 
-pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    // get enough pages to cover the required heap size
-    let page_range = {
-        let first_address = VirtAddr::new(HEAP_START as u64);
-        let last_address = first_address + HEAP_SIZE - 1u64;
-        let first_page = Page::<Size4KiB>::containing_address(first_address);
-        let last_page = Page::<Size4KiB>::containing_address(last_address);
-        Page::range_inclusive(first_page, last_page)
-    };
-
-    // allocate a frame for each page and map it in the page table.
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe {
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
-        }
-    }
+pub fn init_heap(vmm: &VirtualMemoryManager) -> Result<(), MapToError<Size4KiB>> {
+    let heap_start = vmm
+        .reserve(Region::Heap, HEAP_MAX_SIZE)
+        .expect("heap address space is already taken");
+    assert_eq!(heap_start.as_u64(), HEAP_START as u64);
+
+    vmm.map(heap_start, HEAP_SIZE, heap_flags())?;
 
     // resize the heap allocator
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.heap.lock().fallback.init(HEAP_START, HEAP_SIZE);
     }
 
     Ok(())
 }
+
+fn grow_heap(heap: &mut Heap, layout: Layout) -> Result<(), MapToError<Size4KiB>> {
+    // enough for the allocation even in the worst case for alignment, up to the limit
+    let wanted = align_up((layout.size() + layout.align()) as u64, Size4KiB::SIZE) as usize;
+    let room = HEAP_START + HEAP_MAX_SIZE - heap.top();
+    // don't commit frames to growth that can't satisfy the allocation anyway
+    if wanted > room {
+        return Err(MapToError::FrameAllocationFailed);
+    }
+    let size = wanted.max(HEAP_GROWTH).min(room);
+
+    // on failure nothing stays mapped, so the next attempt starts from the same top
+    VMM.map(VirtAddr::new(heap.top() as u64), size, heap_flags())?;
+    unsafe {
+        heap.extend(size);
+    }
+
+    Ok(())
+}
+
+fn heap_flags() -> PageTableFlags {
+    PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+}
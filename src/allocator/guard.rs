@@ -0,0 +1,71 @@
+use core::{alloc::Layout, slice};
+
+// red zones and poisoning are only worth their cost in debug builds
+const ENABLED: bool = cfg!(debug_assertions);
+
+const RED_ZONE_SIZE: usize = 16;
+const CANARY: u8 = 0xCA;
+
+/// Freed memory is filled with this, so reading it after a free is easy to spot.
+pub const POISON: u8 = 0xDD;
+
+/// The layout to request from the heap, with room for a red zone either side of the allocation.
+pub fn block_layout(layout: Layout) -> Option<Layout> {
+    if !ENABLED {
+        return Some(layout);
+    }
+
+    let size = front_padding(layout) + layout.size() + RED_ZONE_SIZE;
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+/// Writes the red zones into a fresh block and returns the pointer to hand out.
+///
+/// # Safety
+/// The block must have been allocated with `block_layout(layout)`.
+pub unsafe fn add_red_zones(block: *mut u8, layout: Layout) -> *mut u8 {
+    if !ENABLED {
+        return block;
+    }
+
+    let ptr = block.add(front_padding(layout));
+    ptr.sub(RED_ZONE_SIZE).write_bytes(CANARY, RED_ZONE_SIZE);
+    ptr.add(layout.size()).write_bytes(CANARY, RED_ZONE_SIZE);
+    ptr
+}
+
+/// Panics if either red zone around the allocation has been damaged, then poisons the whole
+/// block and returns it.
+///
+/// # Safety
+/// The pointer must have come from `add_red_zones` with the same layout.
+pub unsafe fn check_and_poison(ptr: *mut u8, layout: Layout) -> *mut u8 {
+    if !ENABLED {
+        return ptr;
+    }
+
+    let before = slice::from_raw_parts(ptr.sub(RED_ZONE_SIZE), RED_ZONE_SIZE);
+    if before.iter().any(|&byte| byte != CANARY) {
+        panic!(
+            "heap corruption: red zone before allocation at {:#x} was overwritten\n{:?}",
+            ptr as usize, layout
+        );
+    }
+
+    let after = slice::from_raw_parts(ptr.add(layout.size()), RED_ZONE_SIZE);
+    if after.iter().any(|&byte| byte != CANARY) {
+        panic!(
+            "heap corruption: red zone after allocation at {:#x} was overwritten\n{:?}",
+            ptr as usize, layout
+        );
+    }
+
+    let block = ptr.sub(front_padding(layout));
+    block.write_bytes(POISON, block_layout(layout).unwrap().size());
+    block
+}
+
+// the front red zone is padded out so the allocation keeps its alignment
+fn front_padding(layout: Layout) -> usize {
+    RED_ZONE_SIZE.max(layout.align())
+}
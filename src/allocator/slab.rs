@@ -0,0 +1,70 @@
+use core::ptr;
+
+/// A free list of equally sized blocks carved out of larger slabs.
+/// Blocks are handed out and taken back in constant time, and are never returned to the heap.
+pub struct SlabCache {
+    block_size: usize,
+    free_blocks: usize,
+    free_list: Option<&'static mut FreeBlock>,
+}
+
+// a free block stores a pointer to the next one in its first bytes
+struct FreeBlock {
+    next: Option<&'static mut FreeBlock>,
+}
+
+impl SlabCache {
+    pub const fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            free_blocks: 0,
+            free_list: None,
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// The number of bytes sitting unused in this cache's free list.
+    pub fn free_bytes(&self) -> usize {
+        self.free_blocks * self.block_size
+    }
+
+    /// How big a slab to request when this cache runs dry.
+    pub fn slab_size(&self) -> usize {
+        (self.block_size * 8).max(4096)
+    }
+
+    pub fn pop(&mut self) -> Option<*mut u8> {
+        self.free_list.take().map(|block| {
+            self.free_list = block.next.take();
+            self.free_blocks -= 1;
+            block as *mut FreeBlock as *mut u8
+        })
+    }
+
+    /// # Safety
+    /// The caller must guarantee the block is unused, at least `block_size` bytes long, and
+    /// aligned to `block_size`.
+    pub unsafe fn push(&mut self, block: *mut u8) {
+        let block = block as *mut FreeBlock;
+        ptr::write(
+            block,
+            FreeBlock {
+                next: self.free_list.take(),
+            },
+        );
+        self.free_list = Some(&mut *block);
+        self.free_blocks += 1;
+    }
+
+    /// # Safety
+    /// The caller must guarantee the slab is unused, `slab_size` bytes long, and aligned to
+    /// `block_size`.
+    pub unsafe fn add_slab(&mut self, slab: *mut u8) {
+        for offset in (0..self.slab_size()).step_by(self.block_size) {
+            self.push(slab.add(offset));
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use core::fmt;
+
+const MAX_TRACKED_ALLOCATIONS: usize = 1024;
+
+// how many frames to walk up from the allocator to find the code that asked for memory
+const CALLER_DEPTH: usize = 2;
+
+/// An allocation made while leak tracking was switched on.
+#[derive(Debug, Clone, Copy)]
+pub struct TrackedAllocation {
+    pub address: usize,
+    pub size: usize,
+    pub caller: usize,
+}
+
+/// Remembers allocations in a fixed-size table, so that tracking them never needs the heap.
+pub struct AllocationTracker {
+    allocations: [TrackedAllocation; MAX_TRACKED_ALLOCATIONS],
+    len: usize,
+    overflowed: bool,
+}
+
+impl AllocationTracker {
+    pub const fn new() -> Self {
+        Self {
+            allocations: [TrackedAllocation {
+                address: 0,
+                size: 0,
+                caller: 0,
+            }; MAX_TRACKED_ALLOCATIONS],
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.overflowed = false;
+    }
+
+    pub fn live(&self) -> &[TrackedAllocation] {
+        &self.allocations[..self.len]
+    }
+
+    /// Whether some allocations went untracked because the table was full.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    pub fn track(&mut self, allocation: TrackedAllocation) {
+        if self.len == MAX_TRACKED_ALLOCATIONS {
+            self.overflowed = true;
+            return;
+        }
+
+        self.allocations[self.len] = allocation;
+        self.len += 1;
+    }
+
+    pub fn forget(&mut self, address: usize) {
+        if let Some(index) = self
+            .live()
+            .iter()
+            .position(|allocation| allocation.address == address)
+        {
+            self.len -= 1;
+            self.allocations[index] = self.allocations[self.len];
+        }
+    }
+}
+
+impl fmt::Display for TrackedAllocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes at {:#x}, allocated from {:#x}",
+            self.size, self.address, self.caller
+        )
+    }
+}
+
+/// Finds the return address of whoever called into the allocator by walking the frame pointer
+/// chain. This is best effort, inlining can shift the result by a frame or so.
+#[inline(always)]
+pub fn caller_address() -> usize {
+    let mut frame: *const usize;
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) frame, options(nomem, nostack));
+    }
+
+    for _ in 0..CALLER_DEPTH {
+        if frame.is_null() {
+            return 0;
+        }
+        frame = unsafe { *frame } as *const usize;
+    }
+
+    if frame.is_null() {
+        return 0;
+    }
+    unsafe { *frame.add(1) }
+}
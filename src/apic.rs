@@ -0,0 +1,298 @@
+use crate::{
+    acpi::{self, IoApicInfo, Madt},
+    mmio::Mmio,
+    pit, timer,
+};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    structures::paging::{mapper::MapToError, Size4KiB},
+    PhysAddr,
+};
+
+/// Where the local APIC sends interrupts that went away before they could be delivered.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+// local apic registers
+const LOCAL_APIC_ID: usize = 0x20;
+const TASK_PRIORITY: usize = 0x80;
+const END_OF_INTERRUPT: usize = 0xB0;
+const SPURIOUS_INTERRUPT: usize = 0xF0;
+const INTERRUPT_COMMAND_LOW: usize = 0x300;
+const INTERRUPT_COMMAND_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const TIMER_INITIAL_COUNT: usize = 0x380;
+const TIMER_CURRENT_COUNT: usize = 0x390;
+const TIMER_DIVIDE: usize = 0x3E0;
+
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+const CALIBRATION_PERIOD: u64 = 10; // ms
+
+const DELIVERY_INIT: u32 = 0b101 << 8;
+const DELIVERY_STARTUP: u32 = 0b110 << 8;
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+// how long to give a cpu to reset after an init, and to notice a startup ipi
+const INIT_DELAY: u64 = 10; // ms
+const STARTUP_DELAY: u64 = 1; // ms
+
+// i/o apic registers, which are reached indirectly through a select and a window register
+const IO_REGISTER_SELECT: usize = 0x00;
+const IO_WINDOW: usize = 0x10;
+const IO_APIC_VERSION: u32 = 0x01;
+const IO_REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u32 = 1 << 15;
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LOCAL_APIC: spin::Mutex<Option<LocalApic>> = spin::Mutex::new(None);
+static IO_APICS: spin::Mutex<Vec<IoApic>> = spin::Mutex::new(Vec::new());
+
+pub struct LocalApic {
+    registers: Mmio,
+}
+
+impl LocalApic {
+    fn new(madt: &Madt) -> Result<Self, MapToError<Size4KiB>> {
+        Ok(Self {
+            registers: Mmio::new(madt.local_apic, 4096)?,
+        })
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.registers.read::<u32>(LOCAL_APIC_ID) >> 24) as u8
+    }
+
+    fn enable(&mut self) {
+        // accept every priority of interrupt, and send spurious ones somewhere harmless
+        self.registers.write::<u32>(TASK_PRIORITY, 0);
+        self.registers.write::<u32>(
+            SPURIOUS_INTERRUPT,
+            APIC_SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32,
+        );
+    }
+
+    fn send_ipi(&mut self, apic_id: u8, command: u32) {
+        // writing the low half is what sends it, so the destination has to go first
+        self.registers
+            .write::<u32>(INTERRUPT_COMMAND_HIGH, (apic_id as u32) << 24);
+        self.registers.write::<u32>(INTERRUPT_COMMAND_LOW, command);
+        while self.registers.read::<u32>(INTERRUPT_COMMAND_LOW) & DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn end_of_interrupt(&mut self) {
+        self.registers.write::<u32>(END_OF_INTERRUPT, 0);
+    }
+
+    // the timer runs at the bus frequency, which we have to measure against the pit
+    fn calibrate_timer(&mut self) -> u32 {
+        self.registers
+            .write::<u32>(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.registers.write::<u32>(LVT_TIMER, LVT_MASKED);
+        self.registers.write::<u32>(TIMER_INITIAL_COUNT, u32::MAX);
+        pit::wait(CALIBRATION_PERIOD);
+        let elapsed = u32::MAX - self.registers.read::<u32>(TIMER_CURRENT_COUNT);
+        self.registers.write::<u32>(TIMER_INITIAL_COUNT, 0);
+
+        elapsed * (1000 / CALIBRATION_PERIOD) as u32
+    }
+
+    fn start_timer(&mut self, vector: u8) {
+        let count_frequency = self.calibrate_timer();
+        self.registers
+            .write::<u32>(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.registers
+            .write::<u32>(LVT_TIMER, TIMER_PERIODIC | vector as u32);
+        self.registers
+            .write::<u32>(TIMER_INITIAL_COUNT, count_frequency / timer::TICK_FREQUENCY);
+    }
+}
+
+pub struct IoApic {
+    registers: Mmio,
+    gsi_base: u32,
+    redirection_entries: u32,
+}
+
+impl IoApic {
+    fn new(info: &IoApicInfo) -> Result<Self, MapToError<Size4KiB>> {
+        let mut io_apic = Self {
+            registers: Mmio::new(info.address, 0x20)?,
+            gsi_base: info.gsi_base,
+            redirection_entries: 0,
+        };
+        io_apic.redirection_entries = ((io_apic.read(IO_APIC_VERSION) >> 16) & 0xFF) + 1;
+        Ok(io_apic)
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.redirection_entries).contains(&gsi)
+    }
+
+    fn read(&mut self, register: u32) -> u32 {
+        self.registers.write(IO_REGISTER_SELECT, register);
+        self.registers.read(IO_WINDOW)
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        self.registers.write(IO_REGISTER_SELECT, register);
+        self.registers.write(IO_WINDOW, value);
+    }
+
+    fn set_redirection(&mut self, gsi: u32, low: u32, destination: u8) {
+        let register = IO_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        // mask the entry while it's half written
+        self.write(register, REDIRECTION_MASKED);
+        self.write(register + 1, (destination as u32) << 24);
+        self.write(register, low);
+    }
+
+    fn mask_all(&mut self) {
+        for gsi in self.gsi_base..self.gsi_base + self.redirection_entries {
+            self.set_redirection(gsi, REDIRECTION_MASKED, 0);
+        }
+    }
+}
+
+/// Switches interrupt delivery over from the 8259 PICs to the local and I/O APICs described by
+/// the MADT. Every I/O APIC input starts out masked, use `route_irq` to unmask them. The local
+/// APIC timer takes over from the PIT, firing `timer_vector` at `timer::TICK_FREQUENCY`. The
+/// PICs must already be remapped out of the way of the exception vectors so that disabling them
+/// can't cause trouble.
+pub fn init(madt: &Madt, timer_vector: u8) -> Result<(), MapToError<Size4KiB>> {
+    let mut local_apic = LocalApic::new(madt)?;
+    let mut io_apics = madt
+        .io_apics
+        .iter()
+        .map(IoApic::new)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    without_interrupts(|| {
+        for io_apic in &mut io_apics {
+            io_apic.mask_all();
+        }
+        local_apic.enable();
+        local_apic.start_timer(timer_vector);
+
+        *LOCAL_APIC.lock() = Some(local_apic);
+        *IO_APICS.lock() = io_apics;
+        ENABLED.store(true, Ordering::SeqCst);
+    });
+    Ok(())
+}
+
+/// Enables the local APIC of another CPU. Its timer is left alone, so it only gets the
+/// interrupts sent to it directly.
+pub fn init_ap() {
+    if let Some(local_apic) = LOCAL_APIC.lock().as_mut() {
+        local_apic.enable();
+    }
+}
+
+/// Starts the CPU with the given local APIC ID with the INIT-SIPI-SIPI sequence. It wakes up in
+/// real mode at `trampoline`, which must be page aligned and below 1 MiB.
+pub fn start_ap(apic_id: u8, trampoline: PhysAddr) {
+    let vector = (trampoline.as_u64() >> 12) as u32;
+    assert!(
+        trampoline.is_aligned(4096u64) && vector <= 0xFF,
+        "cpus can't start at {:?}",
+        trampoline
+    );
+
+    send_ipi(apic_id, DELIVERY_INIT | LEVEL_ASSERT);
+    pit::wait(INIT_DELAY);
+    // the second startup ipi is only for cpus that missed the first, the rest ignore it
+    for _ in 0..2 {
+        send_ipi(apic_id, DELIVERY_STARTUP | LEVEL_ASSERT | vector);
+        pit::wait(STARTUP_DELAY);
+    }
+}
+
+/// Whether interrupts are coming from the APICs rather than the 8259 PICs.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// The ID of the local APIC of the CPU we're running on.
+pub fn local_apic_id() -> Option<u8> {
+    without_interrupts(|| LOCAL_APIC.lock().as_ref().map(LocalApic::id))
+}
+
+/// Routes an IRQ through the I/O APICs to the given vector on this CPU. IRQs below 16 are the
+/// legacy ISA ones, and take the MADT's overrides into account.
+pub fn route_irq(irq: u8, vector: u8) {
+    let (gsi, mut low) = irq_line(irq);
+    low |= vector as u32;
+    let destination = local_apic_id().expect("local apic isn't initialised");
+    with_io_apic(gsi, |io_apic| {
+        io_apic.set_redirection(gsi, low, destination)
+    });
+}
+
+/// Stops an IRQ from being delivered.
+pub fn mask_irq(irq: u8) {
+    let (gsi, _) = irq_line(irq);
+    with_io_apic(gsi, |io_apic| {
+        io_apic.set_redirection(gsi, REDIRECTION_MASKED, 0)
+    });
+}
+
+/// Acknowledges the interrupt being handled, so the local APIC can deliver the next one.
+pub fn end_of_interrupt() {
+    if let Some(local_apic) = LOCAL_APIC.lock().as_mut() {
+        local_apic.end_of_interrupt();
+    }
+}
+
+// the global system interrupt an irq arrives on, and the redirection flags it needs
+fn irq_line(irq: u8) -> (u32, u32) {
+    let overridden = acpi::madt().and_then(|madt| madt.overrides.iter().find(|o| o.irq == irq));
+    match overridden {
+        Some(o) => {
+            let mut low = 0;
+            if o.active_low() {
+                low |= REDIRECTION_ACTIVE_LOW;
+            }
+            if o.level_triggered() {
+                low |= REDIRECTION_LEVEL_TRIGGERED;
+            }
+            (o.gsi, low)
+        }
+        // isa irqs are edge triggered and active high unless the madt says otherwise, while the
+        // pci ones above them are shared, so level triggered and active low
+        None if irq < 16 => (irq as u32, 0),
+        None => (
+            irq as u32,
+            REDIRECTION_LEVEL_TRIGGERED | REDIRECTION_ACTIVE_LOW,
+        ),
+    }
+}
+
+fn send_ipi(apic_id: u8, command: u32) {
+    without_interrupts(|| {
+        LOCAL_APIC
+            .lock()
+            .as_mut()
+            .expect("local apic isn't initialised")
+            .send_ipi(apic_id, command)
+    });
+}
+
+fn with_io_apic(gsi: u32, f: impl FnOnce(&mut IoApic)) {
+    without_interrupts(|| {
+        let mut io_apics = IO_APICS.lock();
+        let io_apic = io_apics
+            .iter_mut()
+            .find(|io_apic| io_apic.handles(gsi))
+            .unwrap_or_else(|| panic!("no i/o apic handles global system interrupt {}", gsi));
+        f(io_apic);
+    });
+}
@@ -0,0 +1,16 @@
+/// The kernel command line. The bootloader doesn't pass one, so it's baked in when the kernel is
+/// built, from the `FEEBOS_CMDLINE` environment variable, e.g.
+/// `FEEBOS_CMDLINE="keyboard.layout=uk" cargo krun`.
+pub const CMDLINE: &str = match option_env!("FEEBOS_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+/// The value of the first `key=value` option for `key` on a command line.
+pub fn option<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+    cmdline
+        .split_whitespace()
+        .filter_map(|option| option.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
@@ -12,6 +12,7 @@ use x86_64::{
 };
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 
 struct Selectors {
     code_selector: SegmentSelector,
@@ -35,36 +36,74 @@ lazy_static! {
             stack_start + STACK_SIZE
         };
 
+        // page faults get their own stack too, so that running off the end of a kernel stack
+        // into its guard page can be reported
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            const STACK_SIZE: usize = 4096 * 5;
+
+            #[repr(align(16))]
+            struct Stack([u8; STACK_SIZE]);
+            static mut STACK: Stack = Stack([0; STACK_SIZE]);
+
+            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+            stack_start + STACK_SIZE
+        };
+
         tss
     };
 }
 
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+    static ref GDT: CpuTables = CpuTables::new(&TSS);
+}
+
+/// A GDT along with the TSS it points at. Every CPU needs its own, since the TSS holds the
+/// stacks its exception handlers switch to, and is marked busy while loaded.
+pub struct CpuTables {
+    gdt: GlobalDescriptorTable,
+    selectors: Selectors,
+}
+
+impl CpuTables {
+    pub fn new(tss: &'static TaskStateSegment) -> Self {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
         let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+        Self {
             gdt,
-            Selectors {
+            selectors: Selectors {
                 code_selector,
                 data_selector,
                 tss_selector,
             },
-        )
-    };
-}
+        }
+    }
 
-pub fn init() {
-    GDT.0.load();
+    /// Loads the GDT and TSS on the CPU we're running on.
+    pub fn load(&'static self) {
+        self.gdt.load();
 
-    unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        ES::set_reg(GDT.1.data_selector);
-        DS::set_reg(GDT.1.data_selector);
-        SS::set_reg(GDT.1.data_selector);
+        unsafe {
+            CS::set_reg(self.selectors.code_selector);
+            ES::set_reg(self.selectors.data_selector);
+            DS::set_reg(self.selectors.data_selector);
+            SS::set_reg(self.selectors.data_selector);
 
-        load_tss(GDT.1.tss_selector);
+            load_tss(self.selectors.tss_selector);
+        }
     }
 }
+
+/// A TSS for another CPU, with the given stacks for double faults and page faults.
+pub fn new_tss(double_fault_stack: VirtAddr, page_fault_stack: VirtAddr) -> TaskStateSegment {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack;
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = page_fault_stack;
+    tss
+}
+
+/// Loads the boot CPU's GDT and TSS.
+pub fn init() {
+    GDT.load();
+}
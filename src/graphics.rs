@@ -1,9 +1,10 @@
-use bootloader::boot_info::FrameBuffer;
+use alloc::vec::Vec;
+use bootloader::boot_info::{FrameBuffer, PixelFormat};
 use font8x8::{UnicodeFonts, BASIC_FONTS};
 
 use crate::text_buffer::TextBuffer;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     red: u8,
     green: u8,
@@ -14,6 +15,22 @@ pub struct GraphicsContext<'a> {
     fb: Option<&'a mut FrameBuffer>,
 }
 
+/// A small picture drawn from rows of characters: `#` is drawn in the outline colour, `.` in the
+/// fill colour, and anything else is left transparent.
+pub struct Sprite {
+    pub rows: &'static [&'static str],
+    pub outline: Color,
+    pub fill: Color,
+}
+
+/// What was on screen before a sprite was drawn over it, so it can be put back.
+pub struct Background {
+    x: u32,
+    y: u32,
+    width: u32,
+    pixels: Vec<Color>,
+}
+
 impl Color {
     // https://lospec.com/palette-list/sweetie-16
     pub const BLACK: Color = Color::from_hex(0x1A1C2C);
@@ -46,6 +63,39 @@ impl Color {
     }
 }
 
+impl Sprite {
+    pub const CURSOR: Sprite = Sprite {
+        rows: &[
+            "#",
+            "##",
+            "#.#",
+            "#..#",
+            "#...#",
+            "#....#",
+            "#.....#",
+            "#......#",
+            "#.......#",
+            "#........#",
+            "#.....#####",
+            "#..#..#",
+            "#.# #..#",
+            "##  #..#",
+            "#    #..#",
+            "     ####",
+        ],
+        outline: Color::BLACK,
+        fill: Color::WHITE,
+    };
+
+    pub fn width(&self) -> u32 {
+        self.rows.iter().map(|row| row.len()).max().unwrap_or(0) as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.rows.len() as u32
+    }
+}
+
 impl<'a> GraphicsContext<'a> {
     pub fn new() -> Self {
         Self { fb: None }
@@ -55,6 +105,10 @@ impl<'a> GraphicsContext<'a> {
         self.fb = Some(fb);
     }
 
+    pub fn has_framebuffer(&self) -> bool {
+        self.fb.is_some()
+    }
+
     pub fn width(&self) -> u32 {
         self.fb.as_ref().unwrap().info().horizontal_resolution as u32
     }
@@ -84,14 +138,74 @@ impl<'a> GraphicsContext<'a> {
 
         let pixel_index = (y * stride + x) * bpp;
         let colour = match fbinfo.pixel_format {
-            bootloader::boot_info::PixelFormat::RGB => [color.red, color.green, color.blue, 0],
-            bootloader::boot_info::PixelFormat::U8 => [color.red, 0, 0, 0],
+            PixelFormat::RGB => [color.red, color.green, color.blue, 0],
+            PixelFormat::U8 => [color.red, 0, 0, 0],
             _ => [color.blue, color.green, color.red, 0], // we assume BGR as it seems to be quite common
         };
         self.fb.as_mut().unwrap().buffer_mut()[pixel_index..pixel_index + bpp]
             .copy_from_slice(&colour[..bpp]);
     }
 
+    /// Reads back a pixel, or black if it's off screen.
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let fb = self.fb.as_ref().unwrap();
+        let fbinfo = fb.info();
+        let x = x as usize;
+        let y = y as usize;
+        if x >= fbinfo.horizontal_resolution || y >= fbinfo.vertical_resolution {
+            return Color::BLACK;
+        }
+
+        let pixel_index = (y * fbinfo.stride + x) * fbinfo.bytes_per_pixel;
+        let bytes = &fb.buffer()[pixel_index..];
+        match fbinfo.pixel_format {
+            PixelFormat::RGB => Color::new(bytes[0], bytes[1], bytes[2]),
+            PixelFormat::U8 => Color::new(bytes[0], 0, 0),
+            _ => Color::new(bytes[2], bytes[1], bytes[0]),
+        }
+    }
+
+    /// Draws a sprite with its top left corner at `x`, `y`, returning what it covered up.
+    pub fn sprite(&mut self, sprite: &Sprite, x: u32, y: u32) -> Background {
+        let width = sprite.width();
+        let mut pixels = Vec::with_capacity((width * sprite.height()) as usize);
+        for y_offset in 0..sprite.height() {
+            for x_offset in 0..width {
+                pixels.push(self.pixel(x + x_offset, y + y_offset));
+            }
+        }
+
+        for (y_offset, row) in sprite.rows.iter().enumerate() {
+            for (x_offset, c) in row.chars().enumerate() {
+                let colour = match c {
+                    '#' => sprite.outline,
+                    '.' => sprite.fill,
+                    _ => continue,
+                };
+                self.set_pixel(x + x_offset as u32, y + y_offset as u32, colour);
+            }
+        }
+
+        Background {
+            x,
+            y,
+            width,
+            pixels,
+        }
+    }
+
+    /// Puts back what a sprite covered up.
+    pub fn restore(&mut self, background: &Background) {
+        if background.width == 0 {
+            return;
+        }
+        for (i, colour) in background.pixels.iter().enumerate() {
+            let x_offset = i as u32 % background.width;
+            let y_offset = i as u32 / background.width;
+            self.set_pixel(background.x + x_offset, background.y + y_offset, *colour);
+        }
+    }
+
     pub fn char(&mut self, c: char, x: u32, y: u32, fg: Color, bg: Color) {
         if let Some(glyph) = BASIC_FONTS.get(c) {
             for (y_offset, row) in glyph.iter().enumerate() {
@@ -122,16 +236,28 @@ impl<'a> GraphicsContext<'a> {
     ) {
         let width = text_buffer.width;
         for cursor in text_buffer.dirty() {
-            self.char(
-                text_buffer.at(cursor),
-                padding + 8 * (cursor % width) as u32,
-                padding + (8 + line_spacing) * (cursor / width) as u32,
-                fg,
-                bg,
-            );
+            let (x, y) = text_position(cursor, width, padding, line_spacing);
+            self.char(text_buffer.at(cursor), x, y, fg, bg);
         }
         text_buffer.mark_all_clean();
     }
+
+    /// Draws cells taken out of a text buffer `width` characters wide with
+    /// `TextBuffer::take_dirty`, laid out the same way as `text_buffer`.
+    pub fn text_cells(
+        &mut self,
+        cells: &[(usize, char)],
+        width: usize,
+        padding: u32,
+        line_spacing: u32,
+        fg: Color,
+        bg: Color,
+    ) {
+        for &(cursor, c) in cells {
+            let (x, y) = text_position(cursor, width, padding, line_spacing);
+            self.char(c, x, y, fg, bg);
+        }
+    }
 }
 
 impl<'a> Default for GraphicsContext<'a> {
@@ -140,6 +266,14 @@ impl<'a> Default for GraphicsContext<'a> {
     }
 }
 
+// where the character at `cursor` in a text buffer goes on screen
+fn text_position(cursor: usize, width: usize, padding: u32, line_spacing: u32) -> (u32, u32) {
+    (
+        padding + 8 * (cursor % width) as u32,
+        padding + (8 + line_spacing) * (cursor / width) as u32,
+    )
+}
+
 pub fn calculate_text_buffer_size(
     width: u32,
     height: u32,
@@ -1,9 +1,9 @@
-use crate::{gdt, serial_print, serial_println};
+use crate::{acpi, apic, gdt, scheduler, serial_println, stack};
+use core::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::lazy_static;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use pic8259::ChainedPics;
 use x86_64::{
-    instructions::port::Port,
+    instructions::interrupts::without_interrupts,
     registers::control::Cr2,
     structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
 };
@@ -14,29 +14,68 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
-lazy_static! {
-    static ref KEYBOARD: spin::Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = spin::Mutex::new(
-        Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore),
-    );
+/// The number of IRQ lines we handle: the 24 inputs of an I/O APIC, of which the 8259 PICs only
+/// have the first 16.
+pub const IRQ_COUNT: usize = 24;
+const PIC_IRQ_COUNT: u8 = 16;
+
+pub const TIMER_IRQ: u8 = 0;
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const MOUSE_IRQ: u8 = 12;
+// the secondary pic is wired to this line of the primary one
+const CASCADE_IRQ: u8 = 2;
+
+/// Runs in interrupt context whenever its IRQ fires, with the end of interrupt sent after it
+/// returns.
+pub type IrqHandler = fn();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// There is no such line on the interrupt controller in use.
+    InvalidIrq(u8),
+    AlreadyRegistered(u8),
 }
 
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-enum InterruptIndex {
-    Timer = PIC_1_OFFSET,
-    Keyboard,
-}
+static IRQ_HANDLERS: spin::Mutex<[Option<IrqHandler>; IRQ_COUNT]> =
+    spin::Mutex::new([None; IRQ_COUNT]);
 
-impl InterruptIndex {
-    fn as_u8(self) -> u8 {
-        self as u8
-    }
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static IRQ_COUNTS: [AtomicU64; IRQ_COUNT] = [ZERO; IRQ_COUNT];
 
-    fn as_usize(self) -> usize {
-        usize::from(self.as_u8())
-    }
+// irqs arrive on the vectors straight after the exceptions
+fn irq_vector(irq: u8) -> u8 {
+    PIC_1_OFFSET + irq
 }
 
+// every irq line gets its own stub, so the stub knows which one fired
+const IRQ_STUBS: [extern "x86-interrupt" fn(InterruptStackFrame); IRQ_COUNT] = [
+    irq_stub::<0>,
+    irq_stub::<1>,
+    irq_stub::<2>,
+    irq_stub::<3>,
+    irq_stub::<4>,
+    irq_stub::<5>,
+    irq_stub::<6>,
+    irq_stub::<7>,
+    irq_stub::<8>,
+    irq_stub::<9>,
+    irq_stub::<10>,
+    irq_stub::<11>,
+    irq_stub::<12>,
+    irq_stub::<13>,
+    irq_stub::<14>,
+    irq_stub::<15>,
+    irq_stub::<16>,
+    irq_stub::<17>,
+    irq_stub::<18>,
+    irq_stub::<19>,
+    irq_stub::<20>,
+    irq_stub::<21>,
+    irq_stub::<22>,
+    irq_stub::<23>,
+];
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
@@ -64,7 +103,11 @@ lazy_static! {
             .set_handler_fn(stack_segment_fault_handler);
         idt.general_protection_fault
             .set_handler_fn(general_protection_fault_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        }
         idt.x87_floating_point
             .set_handler_fn(x87_floating_point_handler);
         idt.alignment_check.set_handler_fn(alignment_check_handler);
@@ -75,8 +118,10 @@ lazy_static! {
         idt.security_exception
             .set_handler_fn(security_exception_handler);
 
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        for (irq, stub) in IRQ_STUBS.iter().enumerate() {
+            idt[irq_vector(irq as u8) as usize].set_handler_fn(*stub);
+        }
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
 
         idt
     };
@@ -169,11 +214,17 @@ extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
+    let address = Cr2::read();
+    if let Some(stack) = stack::overflowed_stack(address) {
+        panic!(
+            "EXCEPTION: PAGE FAULT ({:?})\nstack overflow in stack {}\n{:#?}",
+            error_code, stack, stack_frame
+        );
+    }
+
     panic!(
         "EXCEPTION: PAGE FAULT ({:?})\nAddress: {:?}\n{:#?}",
-        error_code,
-        Cr2::read(),
-        stack_frame
+        error_code, address, stack_frame
     );
 }
 
@@ -215,41 +266,156 @@ extern "x86-interrupt" fn security_exception_handler(
     );
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+extern "x86-interrupt" fn irq_stub<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    IRQ_COUNTS[IRQ as usize].fetch_add(1, Ordering::Relaxed);
+
+    // copy the handler out, so it can (un)register irqs itself
+    let handler = IRQ_HANDLERS.lock()[IRQ as usize];
+    if let Some(handler) = handler {
+        handler();
     }
+
+    end_of_interrupt(IRQ);
+
+    // only now can we switch threads, or the controller would hold back further interrupts
+    scheduler::preempt_if_requested();
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    let mut port = Port::new(0x60); // PS/2 data port
-    let mut keyboard = KEYBOARD.lock();
+// the local apic doesn't expect an end of interrupt for these
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
 
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => serial_print!("{}", character),
-                DecodedKey::RawKey(key) => serial_print!("{:?}", key),
-            }
+fn end_of_interrupt(irq: u8) {
+    match apic::is_enabled() {
+        true => apic::end_of_interrupt(),
+        false => unsafe { PICS.lock().notify_end_of_interrupt(irq_vector(irq)) },
+    }
+}
+
+/// Calls `handler` every time the given IRQ fires, and unmasks it. Each IRQ has at most one
+/// handler.
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    let line_count = match apic::is_enabled() {
+        true => IRQ_COUNT as u8,
+        false => PIC_IRQ_COUNT,
+    };
+    if irq >= line_count || irq == CASCADE_IRQ {
+        return Err(IrqError::InvalidIrq(irq));
+    }
+
+    without_interrupts(|| {
+        let mut handlers = IRQ_HANDLERS.lock();
+        if handlers[irq as usize].is_some() {
+            return Err(IrqError::AlreadyRegistered(irq));
+        }
+        handlers[irq as usize] = Some(handler);
+        unmask_irq(irq);
+        Ok(())
+    })
+}
+
+/// Masks the given IRQ and removes its handler, returning it.
+pub fn unregister_irq(irq: u8) -> Option<IrqHandler> {
+    without_interrupts(|| {
+        let handler = IRQ_HANDLERS.lock().get_mut(irq as usize)?.take();
+        if handler.is_some() {
+            mask_irq(irq);
         }
+        handler
+    })
+}
+
+/// How many times the given IRQ has fired since boot, handled or not. `None` if there's no
+/// such IRQ.
+pub fn irq_count(irq: u8) -> Option<u64> {
+    IRQ_COUNTS
+        .get(irq as usize)
+        .map(|count| count.load(Ordering::Relaxed))
+}
+
+fn unmask_irq(irq: u8) {
+    match (apic::is_enabled(), irq) {
+        // the local apic timer takes the place of irq 0
+        (true, TIMER_IRQ) => {}
+        (true, _) => apic::route_irq(irq, irq_vector(irq)),
+        (false, _) => set_pic_mask(irq, false),
     }
+}
 
+fn mask_irq(irq: u8) {
+    match (apic::is_enabled(), irq) {
+        (true, TIMER_IRQ) => {}
+        (true, _) => apic::mask_irq(irq),
+        (false, _) => set_pic_mask(irq, true),
+    }
+}
+
+fn set_pic_mask(irq: u8, masked: bool) {
+    let mut pics = PICS.lock();
     unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+        let mut masks = u16::from_le_bytes(pics.read_masks());
+        match masked {
+            true => masks |= 1 << irq,
+            false => masks &= !(1 << irq),
+        }
+        let [primary, secondary] = masks.to_le_bytes();
+        pics.write_masks(primary, secondary);
     }
 }
 
 pub fn init() {
     IDT.load();
 
+    // everything starts out masked, except for the line the secondary pic talks through
     unsafe {
-        PICS.lock().initialize();
+        let mut pics = PICS.lock();
+        pics.initialize();
+        pics.write_masks(!(1 << CASCADE_IRQ), 0xFF);
     }
 }
 
+/// Loads the IDT on another CPU. The handlers and interrupt controllers are shared, so that's
+/// all there is to do.
+pub fn init_ap() {
+    IDT.load();
+}
+
+/// Moves interrupt handling from the 8259 PICs over to the APICs, if the ACPI tables found
+/// through `rsdp_address` describe any. Otherwise the PICs stay in charge. This needs the heap
+/// and virtual memory manager, so it can't happen in `init`.
+pub fn init_apic(rsdp_address: Option<u64>) {
+    let madt = match acpi::init(rsdp_address) {
+        Ok(madt) if !madt.io_apics.is_empty() => madt,
+        Ok(_) => {
+            serial_println!("no I/O APIC found, using the 8259 PICs");
+            return;
+        }
+        Err(error) => {
+            serial_println!("no APIC found ({:?}), using the 8259 PICs", error);
+            return;
+        }
+    };
+
+    // don't let an interrupt from the pics be acknowledged to the apic halfway through
+    without_interrupts(|| {
+        if let Err(error) = apic::init(madt, irq_vector(TIMER_IRQ)) {
+            serial_println!("failed to map the APICs ({:?}), using the 8259 PICs", error);
+            return;
+        }
+
+        unsafe {
+            PICS.lock().disable();
+        }
+
+        // carry over the irqs registered so far
+        let handlers = IRQ_HANDLERS.lock();
+        for irq in 0..IRQ_COUNT as u8 {
+            if handlers[irq as usize].is_some() {
+                unmask_irq(irq);
+            }
+        }
+    });
+}
+
 #[test_case]
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
@@ -1,45 +1,83 @@
 use crate::{
     allocator, gdt,
     graphics::GraphicsContext,
-    interrupts,
-    memory::{self, MemoryRegionsFrameAllocator},
+    interrupts, keyboard,
+    memory::{self, BitmapFrameAllocator},
+    mouse, scheduler, serial_println, smp,
+    sync::{Mutex, MutexGuard},
+    time, timer,
+    vmm::{VirtualMemoryManager, VMM},
 };
 use bootloader::BootInfo;
 use lazy_static::lazy_static;
-use spin::{Mutex, MutexGuard};
 use x86_64::VirtAddr;
 
 pub struct Kernel {
     pub gfx: GraphicsContext<'static>,
+    pub vmm: &'static VirtualMemoryManager,
 }
 
 lazy_static! {
     pub static ref KERNEL: Mutex<Kernel> = Mutex::new(Kernel {
         gfx: GraphicsContext::new(),
+        vmm: &VMM,
     });
 }
 
 impl Kernel {
     pub fn init(&mut self, boot_info: &'static mut BootInfo) {
+        // everything is timestamped from here
+        time::init();
+
         // load GDT and TSS
         gdt::init();
 
         // load IDT and initialise PICs
         interrupts::init();
 
+        // start counting ticks and queueing key presses
+        timer::init();
+        keyboard::init();
+
+        // the mouse is set up by polling, so it has to happen before interrupts are enabled
+        if let Err(error) = mouse::init() {
+            serial_println!("no ps/2 mouse ({:?})", error);
+        }
+
         // enable interrupts
         x86_64::instructions::interrupts::enable();
 
-        // initialise a mapper and frame allocator
+        // enforce no-execute before anything gets mapped with it
+        memory::enable_nx();
+
+        // hand a mapper and frame allocator over to the virtual memory manager
         let physical_memory_offset =
             VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
-        let mut mapper = unsafe { memory::init(physical_memory_offset) };
-        let mut frame_allocator =
-            unsafe { MemoryRegionsFrameAllocator::init(&boot_info.memory_regions) };
+        let mapper = unsafe { memory::init(physical_memory_offset, &boot_info.memory_regions) };
+        let frame_allocator = unsafe {
+            BitmapFrameAllocator::init(&boot_info.memory_regions, physical_memory_offset)
+        };
+        self.vmm.init(mapper, frame_allocator);
 
         // initialise the heap allocator
-        allocator::init_heap(&mut mapper, &mut frame_allocator)
-            .expect("heap initialisation failed");
+        allocator::init_heap(self.vmm).expect("heap initialisation failed");
+
+        // the code running now becomes the first thread
+        scheduler::init();
+
+        // clean up what the bootloader mapped so nothing is both writable and executable
+        self.vmm
+            .enforce_write_xor_execute()
+            .expect("failed to enforce W^X");
+
+        // switch from the PICs to the APICs, now that we can map them
+        interrupts::init_apic(boot_info.rsdp_addr.into_option());
+
+        // the hpet, if there is one, was found along with the apics
+        time::calibrate();
+
+        // start the other cpus, which needs the apics
+        smp::init();
 
         if let Some(framebuffer) = boot_info.framebuffer.as_mut() {
             self.gfx.set_framebuffer(framebuffer);
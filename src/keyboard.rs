@@ -0,0 +1,302 @@
+mod layout;
+
+pub use layout::{parse_handle_control, KeyboardConfig, Layout, ScancodeSet};
+
+use crate::{
+    cmdline,
+    interrupts::{self, KEYBOARD_IRQ},
+    ps2::{self, Ps2Error},
+    scheduler::WaitQueue,
+    serial_println,
+    sync::Mutex,
+    task::Stream,
+};
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+use layout::AnyKeyboard;
+use lazy_static::lazy_static;
+use pc_keyboard::{DecodedKey, KeyCode, KeyEvent, KeyState};
+use x86_64::instructions::interrupts as cpu_interrupts;
+
+const QUEUE_CAPACITY: usize = 128;
+// keyboard command, followed by the set to switch to
+const SELECT_SCANCODE_SET: u8 = 0xF0;
+
+static SCANCODES: ScancodeQueue = ScancodeQueue::new();
+// woken whenever a scancode arrives, see `ScancodeStream`
+static WAKER: spin::Mutex<Option<Waker>> = spin::Mutex::new(None);
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+// threads blocked in `read_key`
+static READERS: WaitQueue = WaitQueue::new();
+// held by whoever is taking scancodes off the queue, since it only supports one consumer at a
+// time, and so keys are decoded in the order their scancodes arrived
+static CONSUMER: Mutex<()> = Mutex::new(());
+
+lazy_static! {
+    static ref DECODER: spin::Mutex<Decoder> = spin::Mutex::new(Decoder {
+        keyboard: AnyKeyboard::new(KeyboardConfig::default()),
+        config: KeyboardConfig::default(),
+        modifiers: Modifiers::default(),
+    });
+}
+
+/// A key that was pressed, along with the modifiers held at the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    pub key: DecodedKey,
+    pub modifiers: Modifiers,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub caps_lock: bool,
+}
+
+impl Modifiers {
+    fn update(&mut self, event: &KeyEvent) {
+        let down = event.state == KeyState::Down;
+        match event.code {
+            KeyCode::ShiftLeft | KeyCode::ShiftRight => self.shift = down,
+            KeyCode::ControlLeft | KeyCode::ControlRight => self.ctrl = down,
+            KeyCode::AltLeft | KeyCode::AltRight => self.alt = down,
+            KeyCode::CapsLock if down => self.caps_lock = !self.caps_lock,
+            _ => {}
+        }
+    }
+}
+
+struct Decoder {
+    keyboard: AnyKeyboard,
+    config: KeyboardConfig,
+    modifiers: Modifiers,
+}
+
+/// A fixed-size ring buffer of raw scancodes, with one producer (the keyboard interrupt) and one
+/// consumer, that never blocks or allocates.
+pub struct ScancodeQueue {
+    buffer: [AtomicU8; QUEUE_CAPACITY],
+    // both only ever count up, the buffer index is taken modulo the capacity
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    overflows: AtomicU64,
+}
+
+impl ScancodeQueue {
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: AtomicU8 = AtomicU8::new(0);
+        Self {
+            buffer: [EMPTY; QUEUE_CAPACITY],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overflows: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds a scancode, or counts an overflow and drops it if the queue is full.
+    pub fn push(&self, scancode: u8) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail - self.head.load(Ordering::Acquire) == QUEUE_CAPACITY {
+            self.overflows.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        self.buffer[tail % QUEUE_CAPACITY].store(scancode, Ordering::Relaxed);
+        self.tail.store(tail + 1, Ordering::Release);
+        true
+    }
+
+    /// Takes the oldest scancode. Only one consumer may pop at a time.
+    pub fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let scancode = self.buffer[head % QUEUE_CAPACITY].load(Ordering::Relaxed);
+        self.head.store(head + 1, Ordering::Release);
+        Some(scancode)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    /// How many scancodes have been dropped because the queue was full.
+    pub fn overflows(&self) -> u64 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for ScancodeQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Starts taking keyboard interrupts, with the layout, scancode set and ctrl handling from the
+/// kernel command line. Switching scancode sets polls the controller, so this has to run with
+/// interrupts disabled.
+pub fn init() {
+    let config = KeyboardConfig::default().with_cmdline(cmdline::CMDLINE);
+    if let Err(error) = configure(config) {
+        serial_println!("failed to configure the keyboard ({:?})", error);
+    }
+    interrupts::register_irq(KEYBOARD_IRQ, keyboard_interrupt).expect("keyboard irq is taken");
+}
+
+// just queue the scancode, decoding it is up to whoever reads the keyboard
+fn keyboard_interrupt() {
+    // switching scancode sets polls for the keyboard's answers, which leaves this interrupt
+    // pending with nothing left to read
+    if ps2::status() & ps2::OUTPUT_FULL == 0 {
+        return;
+    }
+    inject_scancode(ps2::read_data_unchecked());
+}
+
+/// Queues a scancode as though it came from the keyboard.
+pub fn inject_scancode(scancode: u8) -> bool {
+    // the queue only has room for one producer, so keep the keyboard interrupt out while we push
+    let queued = cpu_interrupts::without_interrupts(|| SCANCODES.push(scancode));
+    let waker = cpu_interrupts::without_interrupts(|| WAKER.lock().take());
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+    READERS.wake_all();
+    queued
+}
+
+/// Switches to a different layout, scancode set or way of handling ctrl. Any half-decoded key is
+/// thrown away and held modifiers are forgotten, as though the keyboard had been swapped. A new
+/// scancode set is selected on the keyboard too, and nothing changes if that fails.
+pub fn configure(config: KeyboardConfig) -> Result<(), Ps2Error> {
+    let mut decoder = DECODER.lock();
+    if config.scancode_set != decoder.config.scancode_set {
+        select_scancode_set(config.scancode_set)?;
+    }
+    decoder.keyboard = AnyKeyboard::new(config);
+    decoder.config = config;
+    decoder.modifiers = Modifiers::default();
+    Ok(())
+}
+
+// the keyboard itself always sends set 2, and set 1 comes from the controller translating it.
+// the answers are polled for, so the keyboard interrupt is kept out of the way.
+fn select_scancode_set(set: ScancodeSet) -> Result<(), Ps2Error> {
+    cpu_interrupts::without_interrupts(|| {
+        ps2::send_keyboard(SELECT_SCANCODE_SET)?;
+        ps2::send_keyboard(2)?;
+        let config = ps2::read_config()?;
+        ps2::write_config(match set {
+            ScancodeSet::Set1 => config | ps2::TRANSLATION,
+            ScancodeSet::Set2 => config & !ps2::TRANSLATION,
+        })
+    })
+}
+
+pub fn config() -> KeyboardConfig {
+    DECODER.lock().config
+}
+
+/// Feeds a scancode to the keyboard decoder, returning the key press it completes, if any.
+/// Scancodes have to be decoded in the order they arrived.
+pub fn decode(scancode: u8) -> Option<KeyPress> {
+    let mut decoder = DECODER.lock();
+    let event = decoder.keyboard.add_byte(scancode).ok()??;
+    decoder.modifiers.update(&event);
+    let modifiers = decoder.modifiers;
+    let key = decoder.keyboard.process_keyevent(event)?;
+    Some(KeyPress { key, modifiers })
+}
+
+/// Decodes queued scancodes until a key press comes out, or returns `None` if the queue runs dry
+/// first. Readers take turns, so each key goes to exactly one of them.
+pub fn try_read_key() -> Option<KeyPress> {
+    let _consumer = CONSUMER.lock();
+    while let Some(scancode) = SCANCODES.pop() {
+        if let Some(key) = decode(scancode) {
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// Blocks until a key is pressed.
+pub fn read_key() -> KeyPress {
+    loop {
+        if let Some(key) = try_read_key() {
+            return key;
+        }
+        READERS.wait_until(|| !SCANCODES.is_empty());
+    }
+}
+
+/// How many scancodes have been dropped because nothing was reading the keyboard.
+pub fn overflows() -> u64 {
+    SCANCODES.overflows()
+}
+
+/// The keyboard's scancodes, as they arrive. Only one of these can exist at a time, since reading
+/// from two would split the scancodes between them.
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    pub fn new() -> Self {
+        let taken = STREAM_TAKEN.swap(true, Ordering::SeqCst);
+        assert!(!taken, "there is already a scancode stream");
+        Self { _private: () }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ScancodeStream {
+    fn drop(&mut self) {
+        // take our waker back, so the interrupt doesn't end up dropping it, and maybe freeing it,
+        // long after we're gone
+        let waker = cpu_interrupts::without_interrupts(|| WAKER.lock().take());
+        drop(waker);
+        STREAM_TAKEN.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<u8>> {
+        if let Some(scancode) = pop_scancode() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        // a scancode could arrive between looking and registering, so look again afterwards
+        cpu_interrupts::without_interrupts(|| {
+            *WAKER.lock() = Some(context.waker().clone());
+        });
+        match pop_scancode() {
+            Some(scancode) => {
+                cpu_interrupts::without_interrupts(|| WAKER.lock().take());
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+// for consumers that don't decode, so don't need to hold on to the queue between scancodes
+fn pop_scancode() -> Option<u8> {
+    let _consumer = CONSUMER.lock();
+    SCANCODES.pop()
+}
@@ -0,0 +1,160 @@
+use crate::{cmdline, serial_println};
+use core::str::FromStr;
+use pc_keyboard::{
+    layouts, DecodedKey, Error, HandleControl, KeyEvent, Keyboard, ScancodeSet1, ScancodeSet2,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us104,
+    Uk105,
+    Dvorak104,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+/// Everything about how scancodes turn into keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardConfig {
+    pub layout: Layout,
+    pub scancode_set: ScancodeSet,
+    /// Whether ctrl+letter gives a control character, or the letter.
+    pub handle_control: HandleControl,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        Self {
+            layout: Layout::Us104,
+            scancode_set: ScancodeSet::Set1,
+            handle_control: HandleControl::Ignore,
+        }
+    }
+}
+
+impl KeyboardConfig {
+    /// Applies the `keyboard.layout`, `keyboard.scancodes` and `keyboard.ctrl` options of a kernel
+    /// command line, which take the same values as the shell commands. Values that don't make
+    /// sense are reported and ignored.
+    pub fn with_cmdline(mut self, line: &str) -> Self {
+        if let Some(name) = cmdline::option(line, "keyboard.layout") {
+            match name.parse() {
+                Ok(layout) => self.layout = layout,
+                Err(()) => serial_println!("unknown keyboard.layout {}", name),
+            }
+        }
+        if let Some(name) = cmdline::option(line, "keyboard.scancodes") {
+            match name.parse() {
+                Ok(set) => self.scancode_set = set,
+                Err(()) => serial_println!("unknown keyboard.scancodes {}", name),
+            }
+        }
+        if let Some(name) = cmdline::option(line, "keyboard.ctrl") {
+            match parse_handle_control(name) {
+                Some(handle_control) => self.handle_control = handle_control,
+                None => serial_println!("unknown keyboard.ctrl {}", name),
+            }
+        }
+        self
+    }
+}
+
+impl FromStr for Layout {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        match name {
+            "us" => Ok(Layout::Us104),
+            "uk" => Ok(Layout::Uk105),
+            "dvorak" => Ok(Layout::Dvorak104),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for ScancodeSet {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        match name {
+            "1" => Ok(ScancodeSet::Set1),
+            "2" => Ok(ScancodeSet::Set2),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Parses "ignore" or "unicode", the two ways of handling ctrl+letter.
+pub fn parse_handle_control(name: &str) -> Option<HandleControl> {
+    match name {
+        "ignore" => Some(HandleControl::Ignore),
+        "unicode" => Some(HandleControl::MapLettersToUnicode),
+        _ => None,
+    }
+}
+
+// `pc_keyboard::Keyboard` picks its layout and scancode set at compile time, so keep one of each
+// combination we might want to switch between
+pub enum AnyKeyboard {
+    Us104Set1(Keyboard<layouts::Us104Key, ScancodeSet1>),
+    Us104Set2(Keyboard<layouts::Us104Key, ScancodeSet2>),
+    Uk105Set1(Keyboard<layouts::Uk105Key, ScancodeSet1>),
+    Uk105Set2(Keyboard<layouts::Uk105Key, ScancodeSet2>),
+    Dvorak104Set1(Keyboard<layouts::Dvorak104Key, ScancodeSet1>),
+    Dvorak104Set2(Keyboard<layouts::Dvorak104Key, ScancodeSet2>),
+}
+
+macro_rules! with_keyboard {
+    ($any:expr, $keyboard:ident => $body:expr) => {
+        match $any {
+            AnyKeyboard::Us104Set1($keyboard) => $body,
+            AnyKeyboard::Us104Set2($keyboard) => $body,
+            AnyKeyboard::Uk105Set1($keyboard) => $body,
+            AnyKeyboard::Uk105Set2($keyboard) => $body,
+            AnyKeyboard::Dvorak104Set1($keyboard) => $body,
+            AnyKeyboard::Dvorak104Set2($keyboard) => $body,
+        }
+    };
+}
+
+impl AnyKeyboard {
+    pub fn new(config: KeyboardConfig) -> Self {
+        let control = config.handle_control;
+        match (config.layout, config.scancode_set) {
+            (Layout::Us104, ScancodeSet::Set1) => {
+                AnyKeyboard::Us104Set1(Keyboard::new(layouts::Us104Key, ScancodeSet1, control))
+            }
+            (Layout::Us104, ScancodeSet::Set2) => {
+                AnyKeyboard::Us104Set2(Keyboard::new(layouts::Us104Key, ScancodeSet2, control))
+            }
+            (Layout::Uk105, ScancodeSet::Set1) => {
+                AnyKeyboard::Uk105Set1(Keyboard::new(layouts::Uk105Key, ScancodeSet1, control))
+            }
+            (Layout::Uk105, ScancodeSet::Set2) => {
+                AnyKeyboard::Uk105Set2(Keyboard::new(layouts::Uk105Key, ScancodeSet2, control))
+            }
+            (Layout::Dvorak104, ScancodeSet::Set1) => AnyKeyboard::Dvorak104Set1(Keyboard::new(
+                layouts::Dvorak104Key,
+                ScancodeSet1,
+                control,
+            )),
+            (Layout::Dvorak104, ScancodeSet::Set2) => AnyKeyboard::Dvorak104Set2(Keyboard::new(
+                layouts::Dvorak104Key,
+                ScancodeSet2,
+                control,
+            )),
+        }
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, Error> {
+        with_keyboard!(self, keyboard => keyboard.add_byte(byte))
+    }
+
+    pub fn process_keyevent(&mut self, event: KeyEvent) -> Option<DecodedKey> {
+        with_keyboard!(self, keyboard => keyboard.process_keyevent(event))
+    }
+}
@@ -6,14 +6,33 @@
 #![test_runner(test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+pub mod acpi;
 pub mod allocator;
+pub mod apic;
+pub mod cmdline;
 pub mod gdt;
 pub mod graphics;
 pub mod interrupts;
 pub mod kernel;
+pub mod keyboard;
 pub mod memory;
+pub mod mmio;
+pub mod mouse;
+pub mod panic;
+pub mod pit;
+pub mod ps2;
+pub mod rtc;
+pub mod scheduler;
 pub mod serial_writer;
+pub mod shell;
+pub mod smp;
+pub mod stack;
+pub mod sync;
+pub mod task;
 pub mod text_buffer;
+pub mod time;
+pub mod timer;
+pub mod vmm;
 
 #[macro_use]
 extern crate alloc;
@@ -65,8 +84,9 @@ where
 {
     fn run(&self) {
         serial_print!("{:.<76}", core::any::type_name::<T>());
+        let start = time::Instant::now();
         self();
-        serial_println!("[ok]")
+        serial_println!("[ok] {:?}", start.elapsed())
     }
 }
 
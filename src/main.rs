@@ -6,20 +6,36 @@
 
 extern crate alloc;
 
+use alloc::{string::String, vec::Vec};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use feebos::{
-    graphics::{self, Color},
-    halt_loop,
+    graphics::{self, Background, Color, Sprite},
     kernel::k,
-    println,
-    text_buffer::SHELL,
+    keyboard::{self, ScancodeStream},
+    mouse::{self, MouseStream},
+    print, println, rtc, serial_println,
+    task::{Executor, Stream, Task},
+    text_buffer::{SHELL, SHELL_LINE_SPACING, SHELL_PADDING},
 };
+use pc_keyboard::DecodedKey;
 
 entry_point!(kernel_main);
 
-const SHELL_PADDING: u32 = 8;
-const SHELL_LINE_SPACING: u32 = 2;
+const BACKSPACE: char = '\u{8}';
+
+// where the mouse cursor is, and what it's covering up while it's on screen
+struct Cursor {
+    x: u32,
+    y: u32,
+    background: Option<Background>,
+}
+
+static CURSOR: spin::Mutex<Cursor> = spin::Mutex::new(Cursor {
+    x: 0,
+    y: 0,
+    background: None,
+});
 
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     k().init(boot_info);
@@ -33,37 +49,109 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
         graphics::calculate_text_buffer_size(width, height, SHELL_PADDING, SHELL_LINE_SPACING);
     SHELL.lock().resize(buf_width, buf_height);
 
-    println!("welcome to feebos");
+    println!("welcome to feebos, it's {}", rtc::wall_clock());
 
     k().gfx.clear(Color::BLACK);
-    k().gfx.text_buffer(
-        &mut SHELL.lock(),
+    {
+        let mut cursor = CURSOR.lock();
+        cursor.x = width / 2;
+        cursor.y = height / 2;
+    }
+    draw_shell();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(shell()));
+    if mouse::is_present() {
+        executor.spawn(Task::new(track_mouse()));
+    }
+    executor.run();
+}
+
+// echo whatever is typed into the shell, and run each line as a command
+async fn shell() {
+    let mut scancodes = ScancodeStream::new();
+    let mut line = String::new();
+    while let Some(scancode) = scancodes.next().await {
+        let key = match keyboard::decode(scancode) {
+            Some(press) => press.key,
+            None => continue,
+        };
+
+        match key {
+            DecodedKey::Unicode(BACKSPACE) => {
+                if line.pop().is_some() {
+                    SHELL.lock().delete();
+                }
+            }
+            DecodedKey::Unicode('\n') => {
+                println!();
+                feebos::shell::run_command(&line);
+                line.clear();
+            }
+            DecodedKey::Unicode(character) => {
+                line.push(character);
+                print!("{}", character);
+            }
+            DecodedKey::RawKey(key) => serial_println!("{:?}", key),
+        }
+        draw_shell();
+    }
+}
+
+// move the cursor around the screen as the mouse moves
+async fn track_mouse() {
+    let mut events = MouseStream::new();
+    while let Some(event) = events.next().await {
+        let mut kernel = k();
+        let mut cursor = CURSOR.lock();
+        let x = cursor.x as i32 + event.dx as i32;
+        let y = cursor.y as i32 + event.dy as i32;
+        cursor.x = x.clamp(0, kernel.gfx.width() as i32 - 1) as u32;
+        cursor.y = y.clamp(0, kernel.gfx.height() as i32 - 1) as u32;
+
+        if let Some(background) = cursor.background.take() {
+            kernel.gfx.restore(&background);
+        }
+        cursor.background = Some(kernel.gfx.sprite(&Sprite::CURSOR, cursor.x, cursor.y));
+    }
+}
+
+fn draw_shell() {
+    // copy the changes out rather than drawing with the shell locked, since that keeps
+    // interrupts off and the timer would miss ticks. the room is made before locking, so
+    // copying never allocates.
+    let capacity = SHELL.lock().capacity();
+    let mut cells = Vec::with_capacity(capacity);
+    let width = {
+        let mut shell = SHELL.lock();
+        shell.take_dirty(&mut cells);
+        shell.width
+    };
+
+    let mut kernel = k();
+    let mut cursor = CURSOR.lock();
+
+    // take the cursor off first, or it would put back stale text when it next moves
+    if let Some(background) = cursor.background.take() {
+        kernel.gfx.restore(&background);
+    }
+    kernel.gfx.text_cells(
+        &cells,
+        width,
         SHELL_PADDING,
         SHELL_LINE_SPACING,
         Color::WHITE,
         Color::BLACK,
     );
-
-    halt_loop();
+    if mouse::is_present() {
+        cursor.background = Some(kernel.gfx.sprite(&Sprite::CURSOR, cursor.x, cursor.y));
+    }
 }
 
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    const FG: Color = Color::WHITE;
-    const BG: Color = Color::DARKBLUE;
-
-    SHELL.lock().clear();
-    println!(":<\n");
-    println!("something has gone horribly wrong.");
-    println!("please reboot your computer.\n\n");
-    println!("{}", info);
-
-    k().gfx.clear(BG);
-    k().gfx
-        .text_buffer(&mut SHELL.lock(), SHELL_PADDING, SHELL_LINE_SPACING, FG, BG);
-
-    halt_loop();
+    feebos::panic::handle(info)
 }
 
 #[cfg(test)]
@@ -1,65 +1,419 @@
-use bootloader::boot_info::{MemoryRegionKind, MemoryRegions};
+use crate::serial_println;
+use bootloader::boot_info::{MemoryRegion, MemoryRegionKind, MemoryRegions};
+use core::{fmt, slice};
 use x86_64::{
-    registers::control::Cr3,
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    align_up,
+    registers::{
+        control::{Cr0, Cr0Flags, Cr3},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, OffsetPageTable, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB,
+    },
     PhysAddr, VirtAddr,
 };
 
-pub struct MemoryRegionsFrameAllocator {
-    memory_regions: &'static MemoryRegions,
-    next: usize,
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = 64;
+// frames below 1 MiB are kept back for code that has to run in real mode
+const LOW_MEMORY_WORDS: usize = (0x10_0000 / FRAME_SIZE) as usize / BITS_PER_WORD;
+const LOW_MEMORY_FRAMES: usize = LOW_MEMORY_WORDS * BITS_PER_WORD;
+
+static PHYSICAL_MEMORY_OFFSET: spin::Once<VirtAddr> = spin::Once::new();
+static MEMORY_REGIONS: spin::Once<&'static [MemoryRegion]> = spin::Once::new();
+
+/// Tracks every physical frame up to the end of the highest usable region with a single bit.
+/// A set bit means the frame is either allocated or not usable in the first place.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    // no word between the low memory ones and this one has a free bit in it
+    next_word: usize,
+    // the counts leave out low memory, which is counted on its own
+    total_frames: usize,
+    free_frames: usize,
+    free_low_frames: usize,
 }
 
-impl MemoryRegionsFrameAllocator {
+impl BitmapFrameAllocator {
     /// # Safety
     /// The caller must guarantee the passed memory regions are valid & usable.
-    pub unsafe fn init(memory_regions: &'static MemoryRegions) -> Self {
-        Self {
-            memory_regions,
-            next: 0,
+    /// The caller must guarantee physical memory is mapped at the given offset.
+    pub unsafe fn init(
+        memory_regions: &'static MemoryRegions,
+        physical_memory_offset: VirtAddr,
+    ) -> Self {
+        let usable_regions = || {
+            memory_regions
+                .iter()
+                .filter(|region| region.kind == MemoryRegionKind::Usable)
+        };
+
+        // we need one bit for every frame up to the highest usable address
+        let highest_address = usable_regions().map(|region| region.end).max().unwrap_or(0);
+        let frame_count = (highest_address / FRAME_SIZE) as usize;
+        let word_count = (frame_count + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        let bitmap_size = (word_count * BITS_PER_WORD / 8) as u64;
+
+        // the bitmap lives at the start of the first usable region big enough to hold it
+        let bitmap_start = usable_regions()
+            .map(|region| align_up(region.start, FRAME_SIZE)..region.end)
+            .find(|range| range.end.saturating_sub(range.start) >= bitmap_size)
+            .expect("no usable memory region can hold the frame bitmap")
+            .start;
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let bitmap = slice::from_raw_parts_mut(bitmap_ptr, word_count);
+
+        // start with everything marked as used, then free up the usable regions
+        bitmap.fill(u64::MAX);
+        let mut allocator = Self {
+            bitmap,
+            next_word: LOW_MEMORY_WORDS,
+            total_frames: 0,
+            free_frames: 0,
+            free_low_frames: 0,
+        };
+
+        for region in usable_regions() {
+            let first = (align_up(region.start, FRAME_SIZE) / FRAME_SIZE) as usize;
+            let end = (region.end / FRAME_SIZE) as usize;
+            for frame in first..end {
+                allocator.mark_free(frame);
+            }
+            allocator.total_frames += end.saturating_sub(first.max(LOW_MEMORY_FRAMES));
+        }
+
+        // frame 0 holds the real mode interrupt vectors, so it's never handed out either
+        if !allocator.bitmap.is_empty() {
+            allocator.mark_used(0);
+        }
+
+        // finally, make sure we never hand out the frames holding the bitmap itself
+        let first = (bitmap_start / FRAME_SIZE) as usize;
+        let end = first + ((bitmap_size + FRAME_SIZE - 1) / FRAME_SIZE) as usize;
+        for frame in first..end {
+            allocator.mark_used(frame);
+        }
+
+        allocator
+    }
+
+    /// The number of usable frames, allocated or not. Frames below 1 MiB aren't included, since
+    /// only `allocate_low_frame` hands them out.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// The number of frames `allocate_frame` could still hand out.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// The number of frames `allocate_low_frame` could still hand out.
+    pub fn free_low_frames(&self) -> usize {
+        self.free_low_frames
+    }
+
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+
+    /// Allocates a frame below 1 MiB, where a CPU still in real mode can reach it. These frames
+    /// are never handed out by `allocate_frame`.
+    pub fn allocate_low_frame(&mut self) -> Option<PhysFrame> {
+        let end = LOW_MEMORY_FRAMES.min(self.bitmap.len() * BITS_PER_WORD);
+        let frame = (0..end).find(|&frame| !self.is_used(frame))?;
+        self.mark_used(frame);
+
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            frame as u64 * FRAME_SIZE,
+        )))
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / BITS_PER_WORD] & (1 << (frame % BITS_PER_WORD)) != 0
+    }
+
+    fn mark_used(&mut self, frame: usize) {
+        if !self.is_used(frame) {
+            self.bitmap[frame / BITS_PER_WORD] |= 1 << (frame % BITS_PER_WORD);
+            *self.free_count(frame) -= 1;
         }
     }
 
-    // find all usable frames in our memory regions
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // find regions of kind "usable"
-        self.memory_regions
-            .iter()
-            // get only regions that are usable
-            .filter(|region| region.kind == MemoryRegionKind::Usable)
-            // convert regions to address ranges
-            .map(|region| region.start..region.end)
-            // map each address range to a set of frame start addresses
-            .flat_map(|address_range| address_range.step_by(4096))
-            // get a PhysFrame for each frame start address
-            .map(|address| PhysFrame::containing_address(PhysAddr::new(address)))
+    fn mark_free(&mut self, frame: usize) {
+        if self.is_used(frame) {
+            self.bitmap[frame / BITS_PER_WORD] &= !(1 << (frame % BITS_PER_WORD));
+            *self.free_count(frame) += 1;
+        }
+    }
+
+    // the count a frame belongs in, depending on which pool it's handed out from
+    fn free_count(&mut self, frame: usize) -> &mut usize {
+        match frame < LOW_MEMORY_FRAMES {
+            true => &mut self.free_low_frames,
+            false => &mut self.free_frames,
+        }
     }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for MemoryRegionsFrameAllocator {
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        // skip past any full words to find one with a free bit
+        let word = (self.next_word..self.bitmap.len()).find(|&word| self.bitmap[word] != u64::MAX);
+        self.next_word = word.unwrap_or(self.bitmap.len());
+
+        let frame = self.next_word * BITS_PER_WORD
+            + self.bitmap.get(self.next_word)?.trailing_ones() as usize;
+        self.mark_used(frame);
+
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            frame as u64 * FRAME_SIZE,
+        )))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let frame_index = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        assert!(
+            frame_index < self.bitmap.len() * BITS_PER_WORD,
+            "attempted to free frame {:?}, which is past the end of memory",
+            frame
+        );
+        assert!(
+            self.is_used(frame_index),
+            "attempted to free unused frame {:?}",
+            frame
+        );
+
+        self.mark_free(frame_index);
+        self.next_word = self
+            .next_word
+            .min(frame_index / BITS_PER_WORD)
+            .max(LOW_MEMORY_WORDS);
     }
 }
 
 /// # Safety
 /// The caller must guarantee physical memory is mapped prior to calling this function.
 /// The caller must only call this function once, to avoid aliasing the mut reference to the table.
-pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+pub unsafe fn init(
+    physical_memory_offset: VirtAddr,
+    memory_regions: &'static MemoryRegions,
+) -> OffsetPageTable<'static> {
+    // remember these for dumping the memory map later
+    PHYSICAL_MEMORY_OFFSET.call_once(|| physical_memory_offset);
+    MEMORY_REGIONS.call_once(|| &**memory_regions);
+
     OffsetPageTable::new(
         active_level4_table(physical_memory_offset),
         physical_memory_offset,
     )
 }
 
+/// The address physical memory can be reached at through the bootloader's mapping of it.
+pub fn physical_to_virtual(physical: PhysAddr) -> VirtAddr {
+    let physical_memory_offset = *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("physical memory used before initialisation");
+    physical_memory_offset + physical.as_u64()
+}
+
+/// Makes the CPU enforce the `NO_EXECUTE` page flag, and a missing `WRITABLE` flag even for
+/// kernel code. This has to happen before anything is mapped with `NO_EXECUTE`, since the bit is
+/// reserved while it's off.
+pub fn enable_nx() {
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+}
+
 unsafe fn active_level4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
     // add the offset to the physical address of the L4 table and return it
     let (level4_table_frame, _) = Cr3::read();
-    let physical_addrress = level4_table_frame.start_address();
-    let virtual_address = physical_memory_offset + physical_addrress.as_u64();
-    let level4_table_ptr: *mut PageTable = virtual_address.as_mut_ptr();
+    let level4_table_ptr =
+        page_table_ptr(physical_memory_offset, level4_table_frame.start_address());
 
     &mut *level4_table_ptr // tremendously unsafe :)
 }
+
+fn page_table_ptr(physical_memory_offset: VirtAddr, physical_address: PhysAddr) -> *mut PageTable {
+    let virtual_address = physical_memory_offset + physical_address.as_u64();
+    virtual_address.as_mut_ptr()
+}
+
+/// A run of pages that are contiguous in both virtual and physical memory, with the same flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    pub start: VirtAddr,
+    pub physical: PhysAddr,
+    pub size: u64,
+    /// The flags that actually apply, combined from every level of the page tables.
+    pub flags: PageTableFlags,
+}
+
+impl MappedRange {
+    /// One past the last address. This is a plain number rather than a `VirtAddr`, since a range
+    /// at the top of the lower half ends on the first non-canonical address.
+    pub fn end(&self) -> u64 {
+        self.start.as_u64() + self.size
+    }
+
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        self.start <= address && address.as_u64() < self.end()
+    }
+
+    fn continued_by(&self, next: &MappedRange) -> bool {
+        next.start.as_u64() == self.end()
+            && next.physical == self.physical + self.size
+            && next.flags == self.flags
+    }
+}
+
+/// Walks the active page tables, calling `f` with each mapped range in address order.
+pub fn for_each_mapped_range(mut f: impl FnMut(MappedRange)) {
+    let physical_memory_offset = *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("memory map used before initialisation");
+    let (level4_table_frame, _) = Cr3::read();
+
+    let mut run: Option<MappedRange> = None;
+    let mut merge = |range: MappedRange| match run.as_mut() {
+        Some(current) if current.continued_by(&range) => current.size += range.size,
+        _ => {
+            if let Some(finished) = run.replace(range) {
+                f(finished);
+            }
+        }
+    };
+
+    unsafe {
+        let level4_table =
+            &*page_table_ptr(physical_memory_offset, level4_table_frame.start_address());
+        walk(
+            level4_table,
+            4,
+            0,
+            PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+            physical_memory_offset,
+            &mut merge,
+        );
+    }
+
+    if let Some(finished) = run {
+        f(finished);
+    }
+}
+
+unsafe fn walk(
+    table: &PageTable,
+    level: u8,
+    base: u64,
+    inherited: PageTableFlags,
+    physical_memory_offset: VirtAddr,
+    f: &mut impl FnMut(MappedRange),
+) {
+    let entry_size = 1 << (12 + 9 * (level as u64 - 1));
+    for (index, entry) in table.iter().enumerate() {
+        let entry_flags = entry.flags();
+        if !entry_flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        let address = base | (index as u64 * entry_size);
+
+        // writable and user access must be allowed at every level, no-execute at just one
+        let flags = PageTableFlags::PRESENT
+            | (entry_flags
+                & inherited
+                & (PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE))
+            | ((entry_flags | inherited) & PageTableFlags::NO_EXECUTE);
+
+        // the huge page bit means something else in level 1 (PAT) and level 4 (reserved)
+        let huge = level != 1 && level != 4 && entry_flags.contains(PageTableFlags::HUGE_PAGE);
+        if level == 1 || huge {
+            f(MappedRange {
+                start: VirtAddr::new_truncate(address),
+                physical: entry.addr(),
+                size: entry_size,
+                flags: match huge {
+                    true => flags | PageTableFlags::HUGE_PAGE,
+                    false => flags,
+                },
+            });
+        } else {
+            let next_table = &*page_table_ptr(physical_memory_offset, entry.addr());
+            walk(
+                next_table,
+                level - 1,
+                address,
+                flags,
+                physical_memory_offset,
+                f,
+            );
+        }
+    }
+}
+
+/// Prints every mapped range of the active page tables to serial.
+pub fn dump_page_tables() {
+    serial_println!("mapped ranges:");
+    // this is called when panicking, which might be before we know where the tables are
+    if PHYSICAL_MEMORY_OFFSET.get().is_none() {
+        return;
+    }
+    for_each_mapped_range(|range| serial_println!("  {}", range));
+}
+
+/// Prints the memory regions handed to us by the bootloader to serial.
+pub fn dump_memory_regions() {
+    serial_println!("memory regions:");
+    for region in MEMORY_REGIONS.get().copied().unwrap_or_default() {
+        serial_println!(
+            "  {:#018x}-{:#018x} {} {:?}",
+            region.start,
+            region.end,
+            Size(region.end - region.start),
+            region.kind
+        );
+    }
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |flag, name| match self.flags.contains(flag) {
+            true => name,
+            false => "-",
+        };
+        write!(
+            f,
+            "{:#018x}-{:#018x} -> {:#014x} {} {}{}{}{}",
+            self.start.as_u64(),
+            self.end(),
+            self.physical.as_u64(),
+            Size(self.size),
+            flag(PageTableFlags::WRITABLE, "W"),
+            flag(PageTableFlags::USER_ACCESSIBLE, "U"),
+            flag(PageTableFlags::NO_EXECUTE, "NX"),
+            flag(PageTableFlags::HUGE_PAGE, "H"),
+        )
+    }
+}
+
+// a byte count, shown in the biggest unit it divides evenly into
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut size = self.0;
+        let mut unit = 0;
+        while size >= 1024 && size % 1024 == 0 && unit < units.len() - 1 {
+            size /= 1024;
+            unit += 1;
+        }
+        write!(f, "{} {}", size, units[unit])
+    }
+}
@@ -0,0 +1,80 @@
+use crate::vmm::{Region, VMM};
+use core::mem;
+use volatile::{access::ReadOnly, Volatile};
+use x86_64::{
+    structures::paging::{mapper::MapToError, PageSize, PageTableFlags, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// Maps `len` bytes of device memory at `physical` into the kernel's address space, uncached,
+/// and returns the virtual address of `physical`.
+pub fn map_mmio(physical: PhysAddr, len: usize) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    // mappings are whole pages, so cover everything from the start of the first one
+    let offset = physical.as_u64() % Size4KiB::SIZE;
+    let size = offset as usize + len;
+
+    let start = VMM
+        .reserve(Region::Mmio, size)
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+    VMM.map_physical(start, physical.align_down(Size4KiB::SIZE), size, flags)?;
+
+    Ok(start + offset)
+}
+
+/// A block of device registers, accessed through volatile reads and writes.
+pub struct Mmio {
+    base: VirtAddr,
+    len: usize,
+}
+
+impl Mmio {
+    pub fn new(physical: PhysAddr, len: usize) -> Result<Self, MapToError<Size4KiB>> {
+        Ok(Self {
+            base: map_mmio(physical, len)?,
+            len,
+        })
+    }
+
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    /// The register of type `T` at `offset` bytes into the block.
+    pub fn register<T: Copy>(&mut self, offset: usize) -> Volatile<&mut T> {
+        Volatile::new(unsafe { &mut *self.register_ptr(offset) })
+    }
+
+    pub fn read_only_register<T: Copy>(&self, offset: usize) -> Volatile<&T, ReadOnly> {
+        Volatile::new_read_only(unsafe { &*self.register_ptr(offset) })
+    }
+
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        self.read_only_register(offset).read()
+    }
+
+    pub fn write<T: Copy>(&mut self, offset: usize, value: T) {
+        self.register(offset).write(value);
+    }
+
+    fn register_ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset + mem::size_of::<T>() <= self.len,
+            "register at {:#x} is outside of the {:#x} byte mmio block",
+            offset,
+            self.len
+        );
+
+        let ptr: *mut T = (self.base + offset).as_mut_ptr();
+        assert!(
+            ptr as usize % mem::align_of::<T>() == 0,
+            "register at {:#x} is misaligned",
+            offset
+        );
+        ptr
+    }
+}
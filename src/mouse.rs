@@ -0,0 +1,292 @@
+use crate::{
+    interrupts::{self, MOUSE_IRQ},
+    ps2::{self, Ps2Error},
+    task::Stream,
+};
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+use x86_64::instructions::interrupts as cpu_interrupts;
+
+// mouse commands
+const GET_ID: u8 = 0xF2;
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const ENABLE_REPORTING: u8 = 0xF4;
+const SET_DEFAULTS: u8 = 0xF6;
+
+// a mouse with a scroll wheel reports this id after being sent this magic sequence of sample rates
+const WHEEL_KNOCK: [u8; 3] = [200, 100, 80];
+const WHEEL_ID: u8 = 3;
+
+const QUEUE_CAPACITY: usize = 64;
+
+static PARSER: spin::Mutex<PacketParser> = spin::Mutex::new(PacketParser::new(false));
+static EVENTS: spin::Mutex<EventQueue> = spin::Mutex::new(EventQueue::new());
+static OVERFLOWS: AtomicU64 = AtomicU64::new(0);
+// woken whenever an event arrives, see `MouseStream`
+static WAKER: spin::Mutex<Option<Waker>> = spin::Mutex::new(None);
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// Why `init` couldn't set up the mouse.
+pub type MouseError = Ps2Error;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// What the mouse did since its last report. Movement is in screen directions, so positive `dy`
+/// is down, and positive `scroll` is towards the user.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub scroll: i8,
+    pub buttons: Buttons,
+}
+
+/// Puts bytes from the mouse back together into events. Packets are 3 bytes long, or 4 for a mouse
+/// with a scroll wheel.
+pub struct PacketParser {
+    bytes: [u8; 4],
+    received: usize,
+    wheel: bool,
+}
+
+impl PacketParser {
+    pub const fn new(wheel: bool) -> Self {
+        Self {
+            bytes: [0; 4],
+            received: 0,
+            wheel,
+        }
+    }
+
+    pub fn packet_size(&self) -> usize {
+        match self.wheel {
+            true => 4,
+            false => 3,
+        }
+    }
+
+    /// Adds a byte, returning the event it completes, if any.
+    pub fn push(&mut self, byte: u8) -> Option<MouseEvent> {
+        // the first byte always has bit 3 set, so skip anything else until we're back in step
+        if self.received == 0 && byte & 1 << 3 == 0 {
+            return None;
+        }
+
+        self.bytes[self.received] = byte;
+        self.received += 1;
+        if self.received < self.packet_size() {
+            return None;
+        }
+        self.received = 0;
+
+        let [flags, x, y, z] = self.bytes;
+        // if either axis overflowed the movement is meaningless, but the buttons still count
+        let overflowed = flags & 0xC0 != 0;
+        let dx = match overflowed {
+            true => 0,
+            false => sign_extend(x, flags & 1 << 4 != 0),
+        };
+        let dy = match overflowed {
+            true => 0,
+            false => -sign_extend(y, flags & 1 << 5 != 0),
+        };
+        let scroll = match self.wheel {
+            true => z as i8,
+            false => 0,
+        };
+        Some(MouseEvent {
+            dx,
+            dy,
+            scroll,
+            buttons: Buttons {
+                left: flags & 1 << 0 != 0,
+                right: flags & 1 << 1 != 0,
+                middle: flags & 1 << 2 != 0,
+            },
+        })
+    }
+}
+
+// movement is 9 bits, with the sign kept in the first byte of the packet
+fn sign_extend(value: u8, negative: bool) -> i16 {
+    match negative {
+        true => value as i16 - 0x100,
+        false => value as i16,
+    }
+}
+
+// only touched with interrupts disabled, as the mouse interrupt pushes to it
+struct EventQueue {
+    events: [MouseEvent; QUEUE_CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl EventQueue {
+    const fn new() -> Self {
+        const NOTHING: MouseEvent = MouseEvent {
+            dx: 0,
+            dy: 0,
+            scroll: 0,
+            buttons: Buttons {
+                left: false,
+                right: false,
+                middle: false,
+            },
+        };
+        Self {
+            events: [NOTHING; QUEUE_CAPACITY],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, event: MouseEvent) -> bool {
+        if self.len == QUEUE_CAPACITY {
+            return false;
+        }
+        self.events[(self.head + self.len) % QUEUE_CAPACITY] = event;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<MouseEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head];
+        self.head = (self.head + 1) % QUEUE_CAPACITY;
+        self.len -= 1;
+        Some(event)
+    }
+}
+
+/// Sets up the mouse on the 8042's auxiliary port and starts taking its interrupts. This polls the
+/// controller, so it has to run with interrupts disabled, before the keyboard can get in the way.
+pub fn init() -> Result<(), MouseError> {
+    // throw away anything left over from the firmware
+    ps2::flush()?;
+
+    ps2::write_command(ps2::ENABLE_AUX)?;
+    let config = (ps2::read_config()? | ps2::AUX_INTERRUPT) & !ps2::AUX_CLOCK_DISABLED;
+    ps2::write_config(config)?;
+
+    ps2::send_mouse(SET_DEFAULTS)?;
+    for rate in WHEEL_KNOCK {
+        ps2::send_mouse(SET_SAMPLE_RATE)?;
+        ps2::send_mouse(rate)?;
+    }
+    ps2::send_mouse(GET_ID)?;
+    let wheel = ps2::read_data()? == WHEEL_ID;
+    ps2::send_mouse(ENABLE_REPORTING)?;
+
+    *PARSER.lock() = PacketParser::new(wheel);
+    PRESENT.store(true, Ordering::Relaxed);
+    interrupts::register_irq(MOUSE_IRQ, mouse_interrupt).expect("mouse irq is taken");
+    Ok(())
+}
+
+fn mouse_interrupt() {
+    inject_byte(ps2::read_data_unchecked());
+}
+
+/// Feeds a byte to the packet parser as though it came from the mouse, queueing the event it
+/// completes, if any.
+pub fn inject_byte(byte: u8) {
+    let queued = cpu_interrupts::without_interrupts(|| {
+        let event = PARSER.lock().push(byte)?;
+        Some(EVENTS.lock().push(event))
+    });
+    match queued {
+        None => return,
+        Some(false) => {
+            OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(true) => {}
+    }
+
+    let waker = cpu_interrupts::without_interrupts(|| WAKER.lock().take());
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// Whether `init` found a mouse.
+pub fn is_present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+/// How many bytes the mouse sends per event, 4 if it has a scroll wheel and 3 otherwise.
+pub fn packet_size() -> usize {
+    cpu_interrupts::without_interrupts(|| PARSER.lock().packet_size())
+}
+
+pub fn try_read_event() -> Option<MouseEvent> {
+    cpu_interrupts::without_interrupts(|| EVENTS.lock().pop())
+}
+
+/// How many events have been dropped because nothing was reading the mouse.
+pub fn overflows() -> u64 {
+    OVERFLOWS.load(Ordering::Relaxed)
+}
+
+/// The mouse's events, as they arrive. Only one of these can exist at a time, since reading from
+/// two would split the events between them.
+pub struct MouseStream {
+    _private: (),
+}
+
+impl MouseStream {
+    pub fn new() -> Self {
+        let taken = STREAM_TAKEN.swap(true, Ordering::SeqCst);
+        assert!(!taken, "there is already a mouse stream");
+        Self { _private: () }
+    }
+}
+
+impl Default for MouseStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MouseStream {
+    fn drop(&mut self) {
+        // take our waker back, so the interrupt doesn't end up dropping it, and maybe freeing it,
+        // long after we're gone
+        let waker = cpu_interrupts::without_interrupts(|| WAKER.lock().take());
+        drop(waker);
+        STREAM_TAKEN.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<MouseEvent>> {
+        if let Some(event) = try_read_event() {
+            return Poll::Ready(Some(event));
+        }
+
+        // an event could arrive between looking and registering, so look again afterwards
+        cpu_interrupts::without_interrupts(|| {
+            *WAKER.lock() = Some(context.waker().clone());
+        });
+        match try_read_event() {
+            Some(event) => {
+                cpu_interrupts::without_interrupts(|| WAKER.lock().take());
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{
+    allocator,
+    graphics::Color,
+    halt_loop,
+    kernel::{k, Kernel, KERNEL},
+    memory, println, serial_println,
+    serial_writer::SERIAL,
+    sync::MutexGuard,
+    text_buffer::{SHELL, SHELL_LINE_SPACING, SHELL_PADDING},
+};
+use core::{
+    mem,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::instructions::interrupts;
+
+const FG: Color = Color::WHITE;
+const BG: Color = Color::DARKBLUE;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+// what to do once a panic has been reported, halting unless a test says otherwise
+static FINISH: spin::Once<fn() -> !> = spin::Once::new();
+
+/// The kernel's panic handler. Reports the panic to serial, along with the memory map, then
+/// shows it on screen if there's a framebuffer to draw on, and halts.
+pub fn handle(info: &PanicInfo) -> ! {
+    enter(info);
+
+    // the full memory map doesn't fit on screen, so it goes to serial
+    memory::dump_memory_regions();
+    memory::dump_page_tables();
+
+    // one guard for the whole report. dropping it would wake whoever's waiting for the kernel,
+    // which means taking the scheduler's lock, and the scheduler might be what panicked.
+    let mut kernel = k();
+
+    // before the shell is sized to the framebuffer there's nowhere to draw
+    if SHELL.lock().capacity() == 0 || !kernel.gfx.has_framebuffer() {
+        release(kernel);
+        finish();
+    }
+
+    SHELL.lock().clear();
+    println!(":<\n");
+    println!("something has gone horribly wrong.");
+    println!("please reboot your computer.\n\n");
+    println!("{}", info);
+    if let Some(stats) = allocator::try_stats() {
+        println!("\n{}", stats);
+    }
+
+    kernel.gfx.clear(BG);
+    kernel
+        .gfx
+        .text_buffer(&mut SHELL.lock(), SHELL_PADDING, SHELL_LINE_SPACING, FG, BG);
+
+    release(kernel);
+    finish();
+}
+
+/// Replaces halting as the last thing `handle` does, so tests can check what it did. Only the
+/// first call has any effect.
+pub fn set_finish(finish: fn() -> !) {
+    FINISH.call_once(|| finish);
+}
+
+// gets the handler into a state where it can report the panic, whatever was locked when it
+// happened. interrupts are disabled so nothing else runs, the message is written to serial, and
+// the serial port, shell and kernel are unlocked since whoever held them won't be back. a panic
+// while reporting another one just says so and stops, rather than trying again.
+fn enter(info: &PanicInfo) {
+    interrupts::disable();
+    unsafe { SERIAL.force_unlock() };
+
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        serial_println!("double panic: {}", info);
+        finish();
+    }
+    serial_println!("{}", info);
+
+    unsafe {
+        SHELL.force_unlock();
+        KERNEL.force_unlock();
+    }
+}
+
+// unlocks the kernel without waking anyone up
+fn release(kernel: MutexGuard<Kernel>) {
+    mem::forget(kernel);
+    unsafe { KERNEL.force_unlock() };
+}
+
+fn finish() -> ! {
+    match FINISH.get() {
+        Some(finish) => finish(),
+        None => halt_loop(),
+    }
+}
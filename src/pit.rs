@@ -0,0 +1,54 @@
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+/// The frequency the PIT's counters count down at.
+pub const PIT_FREQUENCY: u64 = 1_193_182; // Hz
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+// also controls the pc speaker, which is hooked up to channel 2
+const CHANNEL_2_GATE: u16 = 0x61;
+
+/// Makes channel 0, which raises IRQ 0, fire at the given frequency.
+pub fn start_periodic(frequency: u32) {
+    let divisor = (PIT_FREQUENCY / frequency as u64) as u16;
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut channel_0: Port<u8> = Port::new(CHANNEL_0);
+
+    without_interrupts(|| unsafe {
+        // channel 0, low then high byte, rate generator
+        command.write(0b0011_0100);
+        channel_0.write(divisor as u8);
+        channel_0.write((divisor >> 8) as u8);
+    });
+}
+
+/// Spins for the given number of milliseconds (at most 54) on channel 2, which can be polled
+/// without interrupts. This makes it handy for calibrating other timers.
+pub fn wait(milliseconds: u64) {
+    let count = PIT_FREQUENCY * milliseconds / 1000;
+    assert!(
+        count <= u16::MAX as u64,
+        "can't wait {} ms on the pit",
+        milliseconds
+    );
+
+    let mut gate: Port<u8> = Port::new(CHANNEL_2_GATE);
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut channel_2: Port<u8> = Port::new(CHANNEL_2);
+
+    unsafe {
+        // gate the channel off, with the speaker disconnected
+        let control = gate.read() & !0b11;
+        gate.write(control);
+
+        // channel 2, low then high byte, interrupt on terminal count
+        command.write(0b1011_0000);
+        channel_2.write(count as u8);
+        channel_2.write((count >> 8) as u8);
+
+        // start counting, and wait for the output to go high at zero
+        gate.write(control | 1);
+        while gate.read() & 0b10_0000 == 0 {}
+    }
+}
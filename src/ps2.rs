@@ -0,0 +1,119 @@
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const COMMAND_PORT: u16 = 0x64;
+
+// status register bits
+pub const OUTPUT_FULL: u8 = 1 << 0;
+const INPUT_FULL: u8 = 1 << 1;
+/// The byte waiting in the output buffer came from the auxiliary port, i.e. the mouse.
+pub const AUX_DATA: u8 = 1 << 5;
+
+// 8042 controller commands
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+pub const ENABLE_AUX: u8 = 0xA8;
+const WRITE_AUX: u8 = 0xD4;
+
+// configuration byte bits
+pub const AUX_INTERRUPT: u8 = 1 << 1;
+pub const AUX_CLOCK_DISABLED: u8 = 1 << 5;
+/// The controller translates the keyboard's scancode set 2 into set 1.
+pub const TRANSLATION: u8 = 1 << 6;
+
+const ACK: u8 = 0xFA;
+
+// how long to poll the controller before giving up on it
+const TIMEOUT_SPINS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The controller or device didn't answer in time, usually because there is no device.
+    Timeout,
+    /// The device answered a command with something other than an acknowledgement.
+    NotAcknowledged(u8),
+}
+
+pub fn status() -> u8 {
+    unsafe { Port::new(COMMAND_PORT).read() }
+}
+
+fn wait_for(ready: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+    for _ in 0..TIMEOUT_SPINS {
+        if ready(status()) {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+pub fn write_command(command: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & INPUT_FULL == 0)?;
+    unsafe { Port::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+pub fn write_data(data: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & INPUT_FULL == 0)?;
+    unsafe { Port::new(DATA_PORT).write(data) };
+    Ok(())
+}
+
+/// Waits for a byte from either port.
+pub fn read_data() -> Result<u8, Ps2Error> {
+    wait_for(|status| status & OUTPUT_FULL != 0)?;
+    Ok(read_data_unchecked())
+}
+
+/// Reads the data port without waiting, for interrupt handlers that know a byte is there.
+pub fn read_data_unchecked() -> u8 {
+    unsafe { Port::new(DATA_PORT).read() }
+}
+
+/// Throws away anything waiting in the output buffer.
+pub fn flush() -> Result<(), Ps2Error> {
+    for _ in 0..TIMEOUT_SPINS {
+        if status() & OUTPUT_FULL == 0 {
+            return Ok(());
+        }
+        read_data_unchecked();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+pub fn read_config() -> Result<u8, Ps2Error> {
+    write_command(READ_CONFIG)?;
+    read_data()
+}
+
+pub fn write_config(config: u8) -> Result<(), Ps2Error> {
+    write_command(WRITE_CONFIG)?;
+    write_data(config)
+}
+
+/// Sends a byte to the keyboard and waits for it to be acknowledged. Anything the mouse sends in
+/// the meantime is thrown away.
+pub fn send_keyboard(byte: u8) -> Result<(), Ps2Error> {
+    write_data(byte)?;
+    loop {
+        wait_for(|status| status & OUTPUT_FULL != 0)?;
+        let from_mouse = status() & AUX_DATA != 0;
+        match read_data_unchecked() {
+            _ if from_mouse => {}
+            ACK => return Ok(()),
+            other => return Err(Ps2Error::NotAcknowledged(other)),
+        }
+    }
+}
+
+/// Sends a byte to the mouse and waits for it to be acknowledged. Bytes written to the data port
+/// go to the keyboard unless the controller is told otherwise.
+pub fn send_mouse(byte: u8) -> Result<(), Ps2Error> {
+    write_command(WRITE_AUX)?;
+    write_data(byte)?;
+    match read_data()? {
+        ACK => Ok(()),
+        other => Err(Ps2Error::NotAcknowledged(other)),
+    }
+}
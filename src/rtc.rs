@@ -0,0 +1,167 @@
+use core::fmt;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+const CMOS_SELECT: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+// set in the select port to keep nmis disabled while we talk to the cmos
+const NMI_DISABLE: u8 = 1 << 7;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+// not guaranteed to exist, but it's where the century lives on pretty much every pc since 1990
+const CENTURY: u8 = 0x32;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const HOURS_24: u8 = 1 << 1;
+const BINARY: u8 = 1 << 2;
+const PM: u8 = 1 << 7;
+
+/// A UTC date and time, to the second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// the registers exactly as the rtc has them, before decoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+/// Reads the current date and time from the CMOS real-time clock, which is assumed to be set to
+/// UTC.
+pub fn wall_clock() -> DateTime {
+    let (raw, status_b) = without_interrupts(|| {
+        // an update can happen between any two reads, so read until we get the same time twice
+        let mut raw = read_raw();
+        loop {
+            let again = read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        (raw, read_register(STATUS_B))
+    });
+
+    decode(raw, status_b)
+}
+
+fn decode(raw: RawTime, status_b: u8) -> DateTime {
+    let binary = status_b & BINARY != 0;
+    let value = |byte: u8| match binary {
+        true => byte,
+        false => (byte >> 4) * 10 + (byte & 0x0F),
+    };
+
+    // in 12 hour mode, the top bit of the hour means pm and midnight is 12
+    let mut hour = value(raw.hour & !PM);
+    if status_b & HOURS_24 == 0 {
+        hour %= 12;
+        if raw.hour & PM != 0 {
+            hour += 12;
+        }
+    }
+
+    let century = match value(raw.century) {
+        century @ 19..=21 => century,
+        _ => 20,
+    };
+
+    DateTime {
+        year: century as u16 * 100 + value(raw.year) as u16,
+        month: value(raw.month),
+        day: value(raw.day),
+        hour,
+        minute: value(raw.minute),
+        second: value(raw.second),
+    }
+}
+
+fn read_raw() -> RawTime {
+    while read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {}
+
+    RawTime {
+        second: read_register(SECONDS),
+        minute: read_register(MINUTES),
+        hour: read_register(HOURS),
+        day: read_register(DAY),
+        month: read_register(MONTH),
+        year: read_register(YEAR),
+        century: read_register(CENTURY),
+    }
+}
+
+fn read_register(register: u8) -> u8 {
+    let mut select: Port<u8> = Port::new(CMOS_SELECT);
+    let mut data: Port<u8> = Port::new(CMOS_DATA);
+    unsafe {
+        select.write(NMI_DISABLE | register);
+        let value = data.read();
+        // let nmis through again
+        select.write(register);
+        value
+    }
+}
+
+#[test_case]
+fn decodes_bcd_12_hour() {
+    let raw = RawTime {
+        second: 0x59,
+        minute: 0x30,
+        hour: PM | 0x12,
+        day: 0x31,
+        month: 0x12,
+        year: 0x99,
+        century: 0x19,
+    };
+    let time = decode(raw, 0);
+    assert_eq!(time.year, 1999);
+    assert_eq!((time.month, time.day), (12, 31));
+    assert_eq!((time.hour, time.minute, time.second), (12, 30, 59));
+
+    let midnight = RawTime { hour: 0x12, ..raw };
+    assert_eq!(decode(midnight, 0).hour, 0);
+}
+
+#[test_case]
+fn decodes_binary_24_hour() {
+    let raw = RawTime {
+        second: 5,
+        minute: 4,
+        hour: 23,
+        day: 2,
+        month: 1,
+        year: 21,
+        century: 0,
+    };
+    let time = decode(raw, BINARY | HOURS_24);
+    assert_eq!(format!("{}", time), "2021-01-02 23:04:05 UTC");
+}
@@ -0,0 +1,488 @@
+mod context;
+mod wait_queue;
+
+pub use wait_queue::WaitQueue;
+
+use crate::{
+    stack::{KernelStack, StackError, DEFAULT_STACK_SIZE},
+    timer,
+};
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::interrupts::{self, without_interrupts};
+
+/// How long a thread gets to run before the timer hands the CPU to the next one of the same
+/// priority.
+pub const TIME_SLICE: Duration = Duration::from_millis(10);
+
+const PRIORITY_COUNT: usize = 3;
+// the scheduler's tables are allocated up front with room for this many threads, so that nothing
+// allocates or frees with interrupts disabled. it matches the number of kernel stacks, which
+// every thread but the boot one needs.
+const MAX_THREADS: usize = 256;
+
+// set when the running thread should give way, and acted on once the interrupt is done
+static PREEMPT: AtomicBool = AtomicBool::new(false);
+static SCHEDULER: spin::Mutex<Option<Scheduler>> = spin::Mutex::new(None);
+// woken whenever a thread finishes, for `JoinHandle::join`
+static FINISHED: WaitQueue = WaitQueue::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    // the thread we booted on is always 0
+    const BOOT: ThreadId = ThreadId(0);
+
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Runnable threads of a higher priority always run first. Those of the same priority take turns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+#[derive(Debug)]
+pub enum SpawnError {
+    /// The thread table is full.
+    TooManyThreads,
+    StackFailed(StackError),
+}
+
+impl From<StackError> for SpawnError {
+    fn from(error: StackError) -> Self {
+        SpawnError::StackFailed(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Runnable,
+    /// Parked on a wait queue.
+    Blocked,
+    /// Waiting for the given tick.
+    Sleeping(u64),
+    Finished,
+}
+
+struct Thread {
+    id: ThreadId,
+    // only held on to until the thread is cleaned up. the boot thread keeps running on the stack
+    // the bootloader gave us
+    _stack: Option<KernelStack>,
+    // where the thread's registers were saved, when it isn't running
+    stack_pointer: u64,
+    entry: Option<Box<dyn FnOnce() + Send>>,
+    priority: Priority,
+    state: State,
+    // nobody is going to join it, so it can be cleaned up as soon as it finishes
+    detached: bool,
+}
+
+// only ever locked with interrupts disabled, since the timer interrupt switches threads
+struct Scheduler {
+    current: ThreadId,
+    // boxed so a thread's saved stack pointer stays put while others come and go
+    #[allow(clippy::vec_box)]
+    threads: Vec<Box<Thread>>,
+    // runnable threads other than the current one, one queue per priority, in the order they'll
+    // run
+    run_queues: [VecDeque<ThreadId>; PRIORITY_COUNT],
+    sleepers: Vec<ThreadId>,
+    // runs when nothing else can, and is never queued
+    idle: ThreadId,
+    // when the current thread was switched to
+    slice_start: u64,
+}
+
+impl Scheduler {
+    fn get(&self, id: ThreadId) -> Option<&Thread> {
+        self.threads
+            .iter()
+            .find(|thread| thread.id == id)
+            .map(|thread| &**thread)
+    }
+
+    fn get_mut(&mut self, id: ThreadId) -> Option<&mut Thread> {
+        self.threads
+            .iter_mut()
+            .find(|thread| thread.id == id)
+            .map(|thread| &mut **thread)
+    }
+
+    fn thread(&mut self, id: ThreadId) -> &mut Thread {
+        self.get_mut(id).expect("no such thread")
+    }
+
+    // takes a thread out of the table, to be dropped once interrupts are enabled again
+    fn remove(&mut self, id: ThreadId) -> Option<Box<Thread>> {
+        let index = self.threads.iter().position(|thread| thread.id == id)?;
+        Some(self.threads.swap_remove(index))
+    }
+
+    // the idle thread ranks below every priority
+    fn rank(&self, id: ThreadId) -> usize {
+        match id == self.idle {
+            true => 0,
+            false => self.get(id).expect("no such thread").priority as usize + 1,
+        }
+    }
+
+    // takes the most important queued thread that ranks at least `min_rank`
+    fn pop_next(&mut self, min_rank: usize) -> Option<ThreadId> {
+        let lowest = min_rank.saturating_sub(1);
+        self.run_queues[lowest..]
+            .iter_mut()
+            .rev()
+            .find_map(|queue| queue.pop_front())
+    }
+
+    fn enqueue(&mut self, id: ThreadId) {
+        if id != self.idle {
+            let priority = self.get(id).expect("no such thread").priority;
+            self.run_queues[priority as usize].push_back(id);
+        }
+    }
+
+    fn wake(&mut self, id: ThreadId) {
+        let thread = match self.get_mut(id) {
+            Some(thread) => thread,
+            None => return,
+        };
+        if !matches!(thread.state, State::Blocked | State::Sleeping(_)) {
+            return;
+        }
+        thread.state = State::Runnable;
+        self.make_ready(id);
+    }
+
+    // queues a runnable thread, and has it take over straight away if it outranks the current one
+    fn make_ready(&mut self, id: ThreadId) {
+        self.enqueue(id);
+        if self.rank(id) > self.rank(self.current) {
+            PREEMPT.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Waits for a thread to finish. Dropping the handle lets the thread carry on by itself.
+#[derive(Debug)]
+pub struct JoinHandle {
+    id: ThreadId,
+}
+
+impl JoinHandle {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        without_interrupts(|| {
+            let scheduler = SCHEDULER.lock();
+            let scheduler = scheduler.as_ref().expect("scheduler not initialised");
+            scheduler.get(self.id).expect("no such thread").state == State::Finished
+        })
+    }
+
+    /// Blocks until the thread has finished.
+    pub fn join(self) {
+        FINISHED.wait_until(|| self.is_finished());
+
+        // its stack is unmapped here rather than with the scheduler locked
+        let thread = without_interrupts(|| {
+            let mut scheduler = SCHEDULER.lock();
+            let scheduler = scheduler.as_mut().expect("scheduler not initialised");
+            scheduler.remove(self.id)
+        });
+        drop(thread);
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            let mut scheduler = SCHEDULER.lock();
+            let scheduler = scheduler.as_mut().expect("scheduler not initialised");
+            if let Some(thread) = scheduler.get_mut(self.id) {
+                thread.detached = true;
+            }
+        });
+    }
+}
+
+/// Turns the code that's running into the boot thread, starts the idle thread, and starts sharing
+/// the CPU between threads. This needs the heap.
+pub fn init() {
+    let boot_thread = Box::new(Thread {
+        id: ThreadId::BOOT,
+        _stack: None,
+        stack_pointer: 0,
+        entry: None,
+        priority: Priority::Normal,
+        state: State::Runnable,
+        detached: true,
+    });
+    let idle = new_thread(Priority::Low, idle_loop).expect("failed to create the idle thread");
+    let idle_id = idle.id;
+    let mut threads = Vec::with_capacity(MAX_THREADS);
+    threads.push(boot_thread);
+    threads.push(idle);
+
+    let scheduler = Scheduler {
+        current: ThreadId::BOOT,
+        threads,
+        run_queues: [
+            VecDeque::with_capacity(MAX_THREADS),
+            VecDeque::with_capacity(MAX_THREADS),
+            VecDeque::with_capacity(MAX_THREADS),
+        ],
+        sleepers: Vec::with_capacity(MAX_THREADS),
+        idle: idle_id,
+        slice_start: timer::ticks(),
+    };
+    without_interrupts(|| *SCHEDULER.lock() = Some(scheduler));
+
+    timer::every(Duration::from_millis(1), tick).expect("no timer left for the scheduler");
+}
+
+/// Whether `init` has been called, so there are threads to switch between.
+pub fn is_running() -> bool {
+    without_interrupts(|| SCHEDULER.lock().is_some())
+}
+
+// halts until the next interrupt, as `halt_loop` does, whenever there is nothing else to do
+fn idle_loop() {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+// called from the timer interrupt every tick
+fn tick() {
+    let mut scheduler = SCHEDULER.lock();
+    let scheduler = match scheduler.as_mut() {
+        Some(scheduler) => scheduler,
+        None => return,
+    };
+
+    let now = timer::ticks();
+    let mut i = 0;
+    while i < scheduler.sleepers.len() {
+        let id = scheduler.sleepers[i];
+        match scheduler.get(id).expect("no such thread").state {
+            State::Sleeping(deadline) if deadline > now => i += 1,
+            _ => {
+                // swapping keeps the allocation, so this never frees in an interrupt handler
+                scheduler.sleepers.swap_remove(i);
+                scheduler.wake(id);
+            }
+        }
+    }
+
+    if now - scheduler.slice_start >= timer::duration_to_ticks(TIME_SLICE) {
+        PREEMPT.store(true, Ordering::Relaxed);
+    }
+}
+
+fn new_thread(
+    priority: Priority,
+    entry: impl FnOnce() + Send + 'static,
+) -> Result<Box<Thread>, StackError> {
+    let stack = KernelStack::new(DEFAULT_STACK_SIZE)?;
+    let stack_pointer = context::prepare_stack(&stack, thread_start);
+    Ok(Box::new(Thread {
+        id: ThreadId::new(),
+        _stack: Some(stack),
+        stack_pointer,
+        entry: Some(Box::new(entry)),
+        priority,
+        state: State::Runnable,
+        detached: false,
+    }))
+}
+
+/// Starts running `entry` in a new kernel thread of normal priority, on its own stack.
+pub fn spawn(entry: impl FnOnce() + Send + 'static) -> Result<JoinHandle, SpawnError> {
+    spawn_with_priority(Priority::Normal, entry)
+}
+
+pub fn spawn_with_priority(
+    priority: Priority,
+    entry: impl FnOnce() + Send + 'static,
+) -> Result<JoinHandle, SpawnError> {
+    reap();
+
+    let thread = new_thread(priority, entry)?;
+    let id = thread.id;
+    let rejected = without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let scheduler = scheduler.as_mut().expect("scheduler not initialised");
+        // the tables never grow, so waking and switching threads never has to allocate
+        if scheduler.threads.len() == MAX_THREADS {
+            return Some(thread);
+        }
+        scheduler.threads.push(thread);
+        scheduler.make_ready(id);
+        None
+    });
+    match rejected {
+        Some(thread) => {
+            // frees its stack, now that interrupts are enabled again
+            drop(thread);
+            Err(SpawnError::TooManyThreads)
+        }
+        None => Ok(JoinHandle { id }),
+    }
+}
+
+// every new thread starts here, straight out of a switch
+extern "C" fn thread_start() -> ! {
+    let entry = {
+        let mut scheduler = SCHEDULER.lock();
+        let scheduler = scheduler.as_mut().unwrap();
+        let current = scheduler.current;
+        scheduler.thread(current).entry.take()
+    };
+
+    // threads are always switched to with interrupts disabled
+    interrupts::enable();
+    if let Some(entry) = entry {
+        entry();
+    }
+    exit();
+}
+
+/// The thread that's running.
+pub fn current() -> ThreadId {
+    without_interrupts(|| match SCHEDULER.lock().as_ref() {
+        Some(scheduler) => scheduler.current,
+        None => ThreadId::BOOT,
+    })
+}
+
+/// Changes the running thread's priority.
+pub fn set_priority(priority: Priority) {
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        let scheduler = scheduler.as_mut().expect("scheduler not initialised");
+        let current = scheduler.current;
+        scheduler.thread(current).priority = priority;
+    });
+    // something more important might be waiting now
+    yield_now();
+}
+
+/// Lets the next runnable thread of the same or higher priority have the CPU, if there is one.
+pub fn yield_now() {
+    without_interrupts(|| reschedule(State::Runnable));
+}
+
+/// Blocks the current thread for at least `duration`, letting others run in the meantime.
+pub fn sleep(duration: Duration) {
+    let deadline = timer::ticks() + timer::duration_to_ticks(duration);
+    without_interrupts(|| {
+        {
+            let mut scheduler = SCHEDULER.lock();
+            let scheduler = scheduler.as_mut().expect("scheduler not initialised");
+            let current = scheduler.current;
+            // there's room for every thread, so this never allocates
+            scheduler.sleepers.push(current);
+        }
+        reschedule(State::Sleeping(deadline));
+    });
+}
+
+/// Finishes the current thread.
+pub fn exit() -> ! {
+    interrupts::disable();
+    FINISHED.wake_all();
+    reschedule(State::Finished);
+    unreachable!("a finished thread was switched back to");
+}
+
+// parks the current thread until something makes it runnable again. interrupts have to be
+// disabled
+fn block_current() {
+    reschedule(State::Blocked);
+}
+
+// makes a blocked or sleeping thread runnable again
+fn make_runnable(id: ThreadId) {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.wake(id);
+    }
+}
+
+/// Switches threads if the current one has used up its time slice or something more important
+/// woke up. This is called at the end of every interrupt, once the controller has been told we're
+/// done with it.
+pub fn preempt_if_requested() {
+    if PREEMPT.swap(false, Ordering::Relaxed) {
+        reschedule(State::Runnable);
+    }
+}
+
+// moves the current thread into the given state and switches to the next one to run. interrupts
+// have to be disabled, or the timer could switch threads halfway through
+fn reschedule(state: State) {
+    let (old_stack_pointer, new_stack_pointer) = {
+        let mut scheduler = SCHEDULER.lock();
+        let scheduler = match scheduler.as_mut() {
+            Some(scheduler) => scheduler,
+            None => return,
+        };
+
+        let current = scheduler.current;
+        let next = match state {
+            // carry on unless something at least as important is waiting
+            State::Runnable => match scheduler.pop_next(scheduler.rank(current)) {
+                Some(next) => next,
+                None => return,
+            },
+            _ => scheduler.pop_next(0).unwrap_or(scheduler.idle),
+        };
+        if state == State::Runnable {
+            scheduler.enqueue(current);
+        }
+
+        let thread = scheduler.thread(current);
+        thread.state = state;
+        let old_stack_pointer = &mut thread.stack_pointer as *mut u64;
+        scheduler.current = next;
+        scheduler.slice_start = timer::ticks();
+        let new_stack_pointer = scheduler.thread(next).stack_pointer;
+        (old_stack_pointer, new_stack_pointer)
+    };
+
+    unsafe { context::switch(old_stack_pointer, new_stack_pointer) };
+}
+
+// cleans up threads that finished with nobody waiting for them, one at a time so each is freed
+// with interrupts enabled
+fn reap() {
+    loop {
+        let finished = without_interrupts(|| {
+            let mut scheduler = SCHEDULER.lock();
+            let scheduler = scheduler.as_mut().expect("scheduler not initialised");
+            let id = scheduler
+                .threads
+                .iter()
+                .find(|thread| thread.detached && thread.state == State::Finished)?
+                .id;
+            scheduler.remove(id)
+        });
+        match finished {
+            Some(thread) => drop(thread),
+            None => return,
+        }
+    }
+}
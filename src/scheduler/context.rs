@@ -0,0 +1,56 @@
+use crate::stack::KernelStack;
+use core::arch::global_asm;
+
+// the callee-saved registers are pushed onto the old thread's stack, then popped off the new one's.
+// everything else was already saved by whoever called us, be it rust code or an interrupt handler
+global_asm!(
+    ".global feebos_switch_context",
+    "feebos_switch_context:",
+    "push rbx",
+    "push rbp",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbp",
+    "pop rbx",
+    "ret",
+);
+
+extern "C" {
+    fn feebos_switch_context(old_stack_pointer: *mut u64, new_stack_pointer: u64);
+}
+
+const SAVED_REGISTERS: usize = 6;
+
+/// Saves the current thread's registers, stores its stack pointer in `old_stack_pointer`, and
+/// carries on from `new_stack_pointer` instead. Returns once something switches back.
+///
+/// # Safety
+/// Interrupts must be disabled, and `new_stack_pointer` must have come from an earlier switch or
+/// `prepare_stack`.
+pub unsafe fn switch(old_stack_pointer: *mut u64, new_stack_pointer: u64) {
+    feebos_switch_context(old_stack_pointer, new_stack_pointer);
+}
+
+/// Lays out a fresh stack so that switching to it calls `entry`, returning the stack pointer.
+pub fn prepare_stack(stack: &KernelStack, entry: extern "C" fn() -> !) -> u64 {
+    let top = stack.top().as_u64() & !0xF;
+    let frame = [0; SAVED_REGISTERS]
+        .into_iter()
+        // `ret` jumps into entry, which then finds a null return address where it expects one,
+        // leaving the stack aligned as though it had been called
+        .chain([entry as usize as u64, 0]);
+
+    let stack_pointer = top - 8 * (SAVED_REGISTERS as u64 + 2);
+    for (i, value) in frame.enumerate() {
+        unsafe { *(stack_pointer as *mut u64).add(i) = value };
+    }
+    stack_pointer
+}
@@ -0,0 +1,109 @@
+use super::{block_current, make_runnable, ThreadId};
+use alloc::vec::Vec;
+use core::mem;
+use x86_64::instructions::interrupts::{self, without_interrupts};
+
+/// Threads parked until something, often an interrupt handler, wakes them up.
+pub struct WaitQueue {
+    // in the order they started waiting
+    waiters: spin::Mutex<Vec<ThreadId>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: spin::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Blocks until `ready` returns true, checking it again every time the queue is woken. No
+    /// interrupt can come between checking and parking, so wakeups aren't lost as long as whatever
+    /// makes `ready` true wakes the queue afterwards.
+    pub fn wait_until(&self, mut ready: impl FnMut() -> bool) {
+        loop {
+            self.make_room();
+            let done = without_interrupts(|| {
+                if ready() {
+                    return true;
+                }
+
+                // before there are threads to switch to, wait for an interrupt like `hlt` always has
+                match super::is_running() {
+                    true => {
+                        let mut waiters = self.waiters.lock();
+                        // pushing must not allocate with interrupts off, so if another thread
+                        // took the room first, go round and make some more
+                        if waiters.len() == waiters.capacity() {
+                            return false;
+                        }
+                        waiters.push(super::current());
+                        drop(waiters);
+                        block_current();
+                    }
+                    false => {
+                        interrupts::enable_and_hlt();
+                        interrupts::disable();
+                    }
+                }
+                false
+            });
+            if done {
+                return;
+            }
+        }
+    }
+
+    // makes sure there's room for one more waiter, allocating with interrupts enabled since the
+    // heap's lock isn't safe to take with them off
+    fn make_room(&self) {
+        let full = without_interrupts(|| {
+            let waiters = self.waiters.lock();
+            match waiters.len() == waiters.capacity() {
+                true => Some(waiters.capacity()),
+                false => None,
+            }
+        });
+        let capacity = match full {
+            Some(capacity) => capacity,
+            None => return,
+        };
+
+        let mut bigger = Vec::with_capacity((capacity * 2).max(4));
+        without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            if bigger.capacity() > waiters.len() {
+                bigger.extend(waiters.drain(..));
+                mem::swap(&mut *waiters, &mut bigger);
+            }
+        });
+        // whichever buffer didn't get used is freed here, with interrupts enabled
+        drop(bigger);
+    }
+
+    /// Wakes the thread that has been waiting longest, returning whether there was one.
+    pub fn wake_one(&self) -> bool {
+        without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            if waiters.is_empty() {
+                return false;
+            }
+            make_runnable(waiters.remove(0));
+            true
+        })
+    }
+
+    pub fn wake_all(&self) {
+        without_interrupts(|| {
+            // draining keeps the allocation, so this is fine from an interrupt handler
+            for thread in self.waiters.lock().drain(..) {
+                make_runnable(thread);
+            }
+        });
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,11 +1,53 @@
-use core::fmt;
+use core::fmt::{self, Write};
 
 use uart_16550::SerialPort;
 
+use crate::time::Instant;
+
 const SERIAL_IO_PORT: u16 = 0x3F8;
 
-pub static SERIAL: spin::Mutex<SerialPort> =
-    spin::Mutex::new(unsafe { SerialPort::new(SERIAL_IO_PORT) });
+pub static SERIAL: spin::Mutex<SerialWriter> = spin::Mutex::new(SerialWriter {
+    port: unsafe { SerialPort::new(SERIAL_IO_PORT) },
+    line_start: true,
+    tee: None,
+});
+
+/// Writes to the serial port, stamping the start of every line with the time since boot.
+pub struct SerialWriter {
+    port: SerialPort,
+    line_start: bool,
+    tee: Option<fn(&str)>,
+}
+
+impl SerialWriter {
+    /// Passes everything written from now on, without the timestamps, to `tee` as well, so tests
+    /// can check what made it out. `None` stops it.
+    pub fn set_tee(&mut self, tee: Option<fn(&str)>) {
+        self.tee = tee;
+    }
+}
+
+impl fmt::Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for line in s.split_inclusive('\n') {
+            if self.line_start {
+                let time = Instant::now().since_start();
+                write!(
+                    self.port,
+                    "[{:>5}.{:06}] ",
+                    time.as_secs(),
+                    time.subsec_micros()
+                )?;
+            }
+            self.port.write_str(line)?;
+            if let Some(tee) = self.tee {
+                tee(line);
+            }
+            self.line_start = line.ends_with('\n');
+        }
+        Ok(())
+    }
+}
 
 #[macro_export]
 macro_rules! serial_print {
@@ -21,8 +63,6 @@ macro_rules! serial_println {
 }
 
 pub fn _print(args: fmt::Arguments) {
-    use core::fmt::Write;
-
     x86_64::instructions::interrupts::without_interrupts(|| {
         SERIAL.lock().write_fmt(args).unwrap();
     });
@@ -0,0 +1,51 @@
+use crate::{
+    keyboard::{self, parse_handle_control},
+    memory, println,
+};
+
+const HELP: &str = "commands:
+  help                       show this
+  layout [us|uk|dvorak]      show or change the keyboard layout
+  scancodes [1|2]            show or change the keyboard's scancode set
+  ctrl [ignore|unicode]      show or change whether ctrl+letter types control characters
+  pagetables                 dump the mapped ranges of the page tables to serial
+  memmap                     dump the bootloader's memory regions to serial";
+
+/// Runs one line typed at the shell.
+pub fn run_command(line: &str) {
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some(command) => command,
+        None => return,
+    };
+    let argument = words.next();
+
+    let mut config = keyboard::config();
+    match (command, argument) {
+        ("help", _) => println!("{}", HELP),
+        ("layout", None) => println!("{:?}", config.layout),
+        ("layout", Some(name)) => match name.parse() {
+            Ok(layout) => config.layout = layout,
+            Err(()) => println!("unknown layout {}", name),
+        },
+        ("scancodes", None) => println!("{:?}", config.scancode_set),
+        ("scancodes", Some(name)) => match name.parse() {
+            Ok(set) => config.scancode_set = set,
+            Err(()) => println!("unknown scancode set {}", name),
+        },
+        ("ctrl", None) => println!("{:?}", config.handle_control),
+        ("ctrl", Some(name)) => match parse_handle_control(name) {
+            Some(handle_control) => config.handle_control = handle_control,
+            None => println!("unknown ctrl handling {}", name),
+        },
+        ("pagetables", _) => memory::dump_page_tables(),
+        ("memmap", _) => memory::dump_memory_regions(),
+        _ => println!("unknown command {}, try help", command),
+    }
+
+    if config != keyboard::config() {
+        if let Err(error) = keyboard::configure(config) {
+            println!("couldn't reconfigure the keyboard ({:?})", error);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+mod trampoline;
+
+use crate::{
+    acpi::{self, Madt},
+    apic,
+    gdt::{self, CpuTables},
+    interrupts, memory, pit, serial_println,
+    stack::{KernelStack, StackError, DEFAULT_STACK_SIZE},
+    vmm::VMM,
+};
+use alloc::boxed::Box;
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use trampoline::Parameters;
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::MapToError, FrameDeallocator, PageSize, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    VirtAddr,
+};
+
+// how long an application processor gets to reach rust after being started
+const START_TIMEOUT: u64 = 100; // ms
+
+// the stacks an application processor's exception handlers switch to
+const EXCEPTION_STACK_SIZE: usize = 4096 * 5;
+
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1);
+// set by each application processor once it's up, by apic id, so a cpu that turns up late
+// can't be mistaken for the one being waited on
+static STARTED: [AtomicBool; 256] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NOT_STARTED: AtomicBool = AtomicBool::new(false);
+    [NOT_STARTED; 256]
+};
+
+#[derive(Debug)]
+pub enum SmpError {
+    /// There's no free frame below 1 MiB to start the other CPUs in.
+    NoLowMemory,
+    /// The trampoline runs in 32 bit mode when it loads CR3, so it can't reach these.
+    PageTablesAbove4GiB,
+    MapFailed(MapToError<Size4KiB>),
+    StackFailed(StackError),
+}
+
+impl From<MapToError<Size4KiB>> for SmpError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        SmpError::MapFailed(error)
+    }
+}
+
+impl From<StackError> for SmpError {
+    fn from(error: StackError) -> Self {
+        SmpError::StackFailed(error)
+    }
+}
+
+// everything an application processor needs, set up by the boot cpu so the application processor
+// doesn't have to allocate. it lives for as long as the cpu does, which is forever.
+struct Cpu {
+    apic_id: u8,
+    tables: CpuTables,
+    stack: KernelStack,
+    _exception_stacks: [KernelStack; 2],
+}
+
+impl Cpu {
+    fn new(apic_id: u8) -> Result<&'static Self, StackError> {
+        let stack = KernelStack::new(DEFAULT_STACK_SIZE)?;
+        let double_fault_stack = KernelStack::new(EXCEPTION_STACK_SIZE)?;
+        let page_fault_stack = KernelStack::new(EXCEPTION_STACK_SIZE)?;
+        let tss = Box::leak(Box::new(gdt::new_tss(
+            double_fault_stack.top(),
+            page_fault_stack.top(),
+        )));
+
+        Ok(Box::leak(Box::new(Self {
+            apic_id,
+            tables: CpuTables::new(tss),
+            stack,
+            _exception_stacks: [double_fault_stack, page_fault_stack],
+        })))
+    }
+}
+
+/// Starts every other enabled CPU in the MADT, and reports how many are online. The application
+/// processors load their own GDT, TSS and IDT, then halt with interrupts disabled, since nothing
+/// is scheduled on them yet. Needs the APICs, so the CPUs stay offline if `interrupts::init_apic`
+/// fell back to the PICs.
+pub fn init() {
+    match acpi::madt() {
+        Some(madt) if apic::is_enabled() => {
+            if let Err(error) = start_application_processors(madt) {
+                serial_println!("failed to start the other cpus ({:?})", error);
+            }
+        }
+        _ => serial_println!("no APIC, so only the boot cpu is used"),
+    }
+    serial_println!("{} CPUs online", cpus_online());
+}
+
+/// The number of CPUs running, including the boot CPU.
+pub fn cpus_online() -> usize {
+    CPUS_ONLINE.load(Ordering::SeqCst)
+}
+
+fn start_application_processors(madt: &Madt) -> Result<(), SmpError> {
+    let (page_table, _) = Cr3::read();
+    if page_table.start_address().as_u64() > u32::MAX as u64 {
+        return Err(SmpError::PageTablesAbove4GiB);
+    }
+
+    let mut frame = install_trampoline()?;
+    let bsp = apic::local_apic_id().expect("local apic isn't initialised");
+    let processors = madt
+        .processors
+        .iter()
+        .filter(|p| p.enabled && p.apic_id != bsp);
+    for processor in processors {
+        let cpu = match Cpu::new(processor.apic_id) {
+            Ok(cpu) => cpu,
+            Err(error) => {
+                remove_trampoline(frame);
+                return Err(error.into());
+            }
+        };
+        unsafe {
+            trampoline::set_parameters(
+                trampoline_page(frame),
+                Parameters {
+                    page_table: page_table.start_address().as_u64(),
+                    stack_pointer: cpu.stack.top().as_u64(),
+                    entry: ap_main as *const () as u64,
+                    argument: cpu as *const Cpu as u64,
+                },
+            );
+        }
+
+        apic::start_ap(processor.apic_id, frame.start_address());
+        if !wait_for_cpu(processor.apic_id) {
+            // it might still turn up later, so leave its trampoline where it is and start the
+            // rest from a fresh one
+            serial_println!("cpu with apic id {} didn't start", processor.apic_id);
+            frame = install_trampoline()?;
+        }
+    }
+
+    remove_trampoline(frame);
+    Ok(())
+}
+
+// copies the trampoline somewhere below 1 MiB, and identity maps it so it can turn paging on
+fn install_trampoline() -> Result<PhysFrame, SmpError> {
+    let frame = VMM
+        .with_frame_allocator(|frame_allocator| frame_allocator.allocate_low_frame())
+        .ok_or(SmpError::NoLowMemory)?;
+    let code = trampoline::code();
+    assert!(code.len() <= Size4KiB::SIZE as usize);
+    unsafe { ptr::copy_nonoverlapping(code.as_ptr(), trampoline_page(frame), code.len()) };
+
+    let identity = VirtAddr::new(frame.start_address().as_u64());
+    if let Err(error) = VMM.map_physical(
+        identity,
+        frame.start_address(),
+        Size4KiB::SIZE as usize,
+        PageTableFlags::PRESENT,
+    ) {
+        free_frame(frame);
+        return Err(error.into());
+    }
+    Ok(frame)
+}
+
+fn trampoline_page(frame: PhysFrame) -> *mut u8 {
+    memory::physical_to_virtual(frame.start_address()).as_mut_ptr()
+}
+
+// only once no cpu can still be running it
+fn remove_trampoline(frame: PhysFrame) {
+    let identity = VirtAddr::new(frame.start_address().as_u64());
+    VMM.unmap_physical(identity, Size4KiB::SIZE as usize)
+        .expect("failed to unmap the trampoline");
+    free_frame(frame);
+}
+
+fn wait_for_cpu(apic_id: u8) -> bool {
+    let started = &STARTED[apic_id as usize];
+    for _ in 0..START_TIMEOUT {
+        if started.load(Ordering::SeqCst) {
+            return true;
+        }
+        pit::wait(1);
+    }
+    started.load(Ordering::SeqCst)
+}
+
+fn free_frame(frame: PhysFrame) {
+    VMM.with_frame_allocator(|frame_allocator| unsafe { frame_allocator.deallocate_frame(frame) });
+}
+
+// where the trampoline leaves an application processor, on its own stack
+extern "C" fn ap_main(cpu: &'static Cpu) -> ! {
+    cpu.tables.load();
+    interrupts::init_ap();
+    apic::init_ap();
+
+    CPUS_ONLINE.fetch_add(1, Ordering::SeqCst);
+    STARTED[cpu.apic_id as usize].store(true, Ordering::SeqCst);
+    loop {
+        x86_64::instructions::interrupts::disable();
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,121 @@
+use core::{arch::global_asm, ptr};
+
+// where an application processor starts, in real mode at the start of a page below 1 MiB. it
+// patches the absolute addresses in here once it knows which page that is, switches to
+// protected mode and then long mode using the kernel's page tables, and calls into rust on the
+// stack it was given. the kernel's page tables have to identity map the page for the last step.
+global_asm!(
+    ".balign 8",
+    ".global feebos_trampoline_start",
+    ".global feebos_trampoline_end",
+    ".code16",
+    "feebos_trampoline_start:",
+    "    jmp 1f",
+    // `Parameters`, filled in by the cpu starting us
+    ".balign 8",
+    "trampoline_parameters:",
+    "    .quad 0, 0, 0, 0",
+    "1:",
+    "    cli",
+    "    cld",
+    "    mov %cs, %ax",
+    "    mov %ax, %ds",
+    "    xor %ebx, %ebx",
+    "    mov %ax, %bx",
+    "    shl $4, %ebx",
+    "    lea (trampoline_gdt - feebos_trampoline_start)(%ebx), %eax",
+    "    mov %eax, trampoline_gdt_pointer - feebos_trampoline_start + 2",
+    "    lea (trampoline_protected_mode - feebos_trampoline_start)(%ebx), %eax",
+    "    mov %eax, trampoline_protected_mode_pointer - feebos_trampoline_start",
+    "    lea (trampoline_long_mode - feebos_trampoline_start)(%ebx), %eax",
+    "    mov %eax, trampoline_long_mode_pointer - feebos_trampoline_start",
+    "    lgdtl trampoline_gdt_pointer - feebos_trampoline_start",
+    "    mov %cr0, %eax",
+    "    or $1, %eax",
+    "    mov %eax, %cr0",
+    "    ljmpl *trampoline_protected_mode_pointer - feebos_trampoline_start",
+    ".code32",
+    "trampoline_protected_mode:",
+    "    mov $0x10, %ax",
+    "    mov %ax, %ds",
+    "    mov %ax, %es",
+    "    mov %ax, %ss",
+    // physical address extension, then the kernel's page tables
+    "    mov %cr4, %eax",
+    "    or $(1 << 5), %eax",
+    "    mov %eax, %cr4",
+    "    mov (trampoline_parameters - feebos_trampoline_start)(%ebx), %eax",
+    "    mov %eax, %cr3",
+    // long mode and no-execute in the efer
+    "    mov $0xC0000080, %ecx",
+    "    rdmsr",
+    "    or $((1 << 8) | (1 << 11)), %eax",
+    "    wrmsr",
+    // paging and write protection
+    "    mov %cr0, %eax",
+    "    or $((1 << 31) | (1 << 16)), %eax",
+    "    mov %eax, %cr0",
+    "    ljmpl *(trampoline_long_mode_pointer - feebos_trampoline_start)(%ebx)",
+    ".code64",
+    "trampoline_long_mode:",
+    // the top half of every register is undefined after the switch
+    "    mov %ebx, %ebx",
+    "    mov (trampoline_parameters - feebos_trampoline_start + 8)(%rbx), %rsp",
+    "    mov (trampoline_parameters - feebos_trampoline_start + 24)(%rbx), %rdi",
+    "    call *(trampoline_parameters - feebos_trampoline_start + 16)(%rbx)",
+    "    ud2",
+    ".balign 8",
+    "trampoline_gdt:",
+    "    .quad 0",
+    "    .quad 0x00CF9A000000FFFF", // 32 bit code
+    "    .quad 0x00CF92000000FFFF", // data
+    "    .quad 0x00AF9A000000FFFF", // 64 bit code
+    "trampoline_gdt_pointer:",
+    "    .word 4 * 8 - 1",
+    "    .long 0",
+    "trampoline_protected_mode_pointer:",
+    "    .long 0",
+    "    .word 0x08",
+    "trampoline_long_mode_pointer:",
+    "    .long 0",
+    "    .word 0x18",
+    "feebos_trampoline_end:",
+    options(att_syntax)
+);
+
+extern "C" {
+    static feebos_trampoline_start: u8;
+    static feebos_trampoline_end: u8;
+}
+
+// where `trampoline_parameters` sits in the trampoline
+const PARAMETERS_OFFSET: usize = 8;
+
+/// What an application processor needs to get into rust.
+#[repr(C)]
+pub struct Parameters {
+    /// The physical address of the top level page table, which has to be below 4 GiB.
+    pub page_table: u64,
+    pub stack_pointer: u64,
+    pub entry: u64,
+    /// Passed to `entry` as its first argument.
+    pub argument: u64,
+}
+
+/// The trampoline's code, to be copied into a page below 1 MiB.
+pub fn code() -> &'static [u8] {
+    unsafe {
+        let start = ptr::addr_of!(feebos_trampoline_start);
+        let end = ptr::addr_of!(feebos_trampoline_end);
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+/// Fills in the parameters of a trampoline copied to `page`.
+///
+/// # Safety
+/// `page` must point to a writable copy of `code()`, which no cpu is still reading the
+/// parameters of.
+pub unsafe fn set_parameters(page: *mut u8, parameters: Parameters) {
+    ptr::write_volatile(page.add(PARAMETERS_OFFSET) as *mut Parameters, parameters);
+}
@@ -0,0 +1,123 @@
+use crate::vmm::{Region, VMM};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::{
+    structures::paging::{mapper::MapToError, PageSize, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+pub const DEFAULT_STACK_SIZE: usize = 16 * 4096; // 64 KiB
+
+const MAX_STACKS: usize = 256;
+const GUARD_PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// a stack's id, and its guard page once the stack is mapped
+type Slot = Option<(usize, Option<VirtAddr>)>;
+
+// the guard page of every live stack, so page faults can tell which one overflowed. a slot is
+// claimed before the stack is mapped, and only gets its guard page once that's worked.
+static GUARD_PAGES: spin::Mutex<[Slot; MAX_STACKS]> = spin::Mutex::new([None; MAX_STACKS]);
+
+#[derive(Debug)]
+pub enum StackError {
+    /// Every kernel stack slot is in use.
+    TooManyStacks,
+    MapFailed(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for StackError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        StackError::MapFailed(error)
+    }
+}
+
+/// A kernel stack mapped in the stacks region, with an unmapped guard page just below it.
+#[derive(Debug)]
+pub struct KernelStack {
+    id: usize,
+    bottom: VirtAddr,
+    size: usize,
+}
+
+impl KernelStack {
+    pub fn new(size: usize) -> Result<Self, StackError> {
+        // claim a slot first, so a full table fails before any memory is touched
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let slot = {
+            let mut guard_pages = GUARD_PAGES.lock();
+            let slot = guard_pages
+                .iter()
+                .position(|slot| slot.is_none())
+                .ok_or(StackError::TooManyStacks)?;
+            guard_pages[slot] = Some((id, None));
+            slot
+        };
+
+        match map(size) {
+            Ok(guard_page) => {
+                GUARD_PAGES.lock()[slot] = Some((id, Some(guard_page)));
+                Ok(Self {
+                    id,
+                    bottom: guard_page + GUARD_PAGE_SIZE,
+                    size,
+                })
+            }
+            Err(error) => {
+                GUARD_PAGES.lock()[slot] = None;
+                Err(error.into())
+            }
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The initial stack pointer. Stacks grow down, so this is one past the highest address.
+    pub fn top(&self) -> VirtAddr {
+        self.bottom + self.size
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        // the guard page stays reserved, but the stack's frames are freed
+        VMM.unmap(self.bottom, self.size)
+            .expect("failed to unmap kernel stack");
+
+        let mut guard_pages = GUARD_PAGES.lock();
+        if let Some(slot) = guard_pages
+            .iter_mut()
+            .find(|slot| matches!(slot, Some((id, _)) if *id == self.id))
+        {
+            *slot = None;
+        }
+    }
+}
+
+// maps a stack of the given size with a guard page below it, returning the guard page. `map`
+// unmaps whatever it managed if it fails, and the address space is never given back anyway.
+fn map(size: usize) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let guard_page = VMM
+        .reserve(Region::Stacks, GUARD_PAGE_SIZE as usize + size)
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    VMM.map(
+        guard_page + GUARD_PAGE_SIZE,
+        size,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+    )?;
+    Ok(guard_page)
+}
+
+/// The id of the stack whose guard page contains the given address, if any. This is called from
+/// the page fault handler, so it gives up rather than waiting for the lock.
+pub fn overflowed_stack(address: VirtAddr) -> Option<usize> {
+    let guard_pages = GUARD_PAGES.try_lock()?;
+    guard_pages
+        .iter()
+        .flatten()
+        .filter_map(|(id, guard_page)| Some((*id, (*guard_page)?)))
+        .find(|(_, guard_page)| (*guard_page..*guard_page + GUARD_PAGE_SIZE).contains(&address))
+        .map(|(id, _)| id)
+}
@@ -0,0 +1,11 @@
+mod condvar;
+mod mutex;
+mod rwlock;
+mod semaphore;
+mod spinlock;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use spinlock::{IrqSpinlock, IrqSpinlockGuard};
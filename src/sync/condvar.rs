@@ -0,0 +1,59 @@
+use super::MutexGuard;
+use crate::scheduler::WaitQueue;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Lets threads sleep until another one tells them something guarded by a `Mutex` has changed.
+pub struct Condvar {
+    // bumped by every notification, so a waiter can tell if it missed one while unlocking
+    generation: AtomicU64,
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            generation: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks the mutex and sleeps until notified, then locks it again. Like any condition
+    /// variable this can wake up spuriously, so check the condition again afterwards.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let generation = self.generation.load(Ordering::Acquire);
+        drop(guard);
+
+        self.waiters
+            .wait_until(|| self.generation.load(Ordering::Acquire) != generation);
+        mutex.lock()
+    }
+
+    /// Waits for as long as `condition` holds.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
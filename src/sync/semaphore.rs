@@ -0,0 +1,43 @@
+use crate::scheduler::WaitQueue;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A count of permits. Taking one when there are none left blocks until one is given back.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Blocks until there's a permit, then takes it.
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            self.waiters
+                .wait_until(|| self.permits.load(Ordering::Relaxed) > 0);
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Gives a permit back, waking a thread waiting for one.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.wake_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,90 @@
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+use x86_64::instructions::interrupts;
+
+/// A spinlock that disables interrupts while it's held, so an interrupt handler can never find it
+/// locked by the code it interrupted. Only for short critical sections.
+pub struct IrqSpinlock<T: ?Sized> {
+    inner: spin::Mutex<T>,
+}
+
+pub struct IrqSpinlockGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    // whether to turn interrupts back on once we're unlocked
+    interrupts_were_enabled: bool,
+}
+
+impl<T> IrqSpinlock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: spin::Mutex::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqSpinlock<T> {
+    pub fn lock(&self) -> IrqSpinlockGuard<'_, T> {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        IrqSpinlockGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_were_enabled,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSpinlockGuard<'_, T>> {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqSpinlockGuard {
+                guard: ManuallyDrop::new(guard),
+                interrupts_were_enabled,
+            }),
+            None => {
+                if interrupts_were_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Unlocks the spinlock out from under whoever holds it, for when they're never going to.
+    /// Interrupts are left as they are.
+    ///
+    /// # Safety
+    /// The holder's guard must never be used or dropped again.
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
+}
+
+impl<T: ?Sized> Deref for IrqSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        // unlock before enabling interrupts, or a handler could still find it locked
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_were_enabled {
+            interrupts::enable();
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use super::{Task, TaskId};
+use alloc::{collections::BTreeMap, collections::VecDeque, sync::Arc, task::Wake};
+use core::task::{Context, Poll, Waker};
+use x86_64::instructions::interrupts;
+
+// wakers can run in interrupt handlers, where growing the queue would mean allocating
+const QUEUE_CAPACITY: usize = 128;
+
+/// Runs tasks whenever they're woken, and halts the CPU while none are.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    queue: Arc<TaskQueue>,
+    wakers: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            queue: Arc::new(TaskQueue::new()),
+            wakers: BTreeMap::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        let id = task.id;
+        if self.tasks.insert(id, task).is_some() {
+            panic!("task {:?} was spawned twice", id);
+        }
+        self.queue.push(id);
+    }
+
+    /// Runs tasks forever.
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Runs tasks until every one of them has finished.
+    pub fn run_until_idle(&mut self) {
+        while !self.tasks.is_empty() {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        while let Some(id) = self.queue.pop() {
+            // a task can be woken again after it has finished
+            let task = match self.tasks.get_mut(&id) {
+                Some(task) => task,
+                None => continue,
+            };
+
+            let queue = &self.queue;
+            let waker = self
+                .wakers
+                .entry(id)
+                .or_insert_with(|| TaskWaker::waker(id, queue.clone()));
+            let mut context = Context::from_waker(waker);
+            if task.poll(&mut context) == Poll::Ready(()) {
+                self.tasks.remove(&id);
+                self.wakers.remove(&id);
+            }
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        // an interrupt between checking the queue and halting would leave us asleep with work
+        // to do, so only let interrupts back in as we halt
+        interrupts::disable();
+        match self.queue.is_empty() {
+            true => interrupts::enable_and_hlt(),
+            false => interrupts::enable(),
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the ids of tasks that have been woken
+struct TaskQueue {
+    ids: spin::Mutex<VecDeque<TaskId>>,
+}
+
+impl TaskQueue {
+    fn new() -> Self {
+        Self {
+            ids: spin::Mutex::new(VecDeque::with_capacity(QUEUE_CAPACITY)),
+        }
+    }
+
+    fn push(&self, id: TaskId) {
+        interrupts::without_interrupts(|| {
+            let mut ids = self.ids.lock();
+            assert!(ids.len() < QUEUE_CAPACITY, "task queue is full");
+            ids.push_back(id);
+        });
+    }
+
+    fn pop(&self) -> Option<TaskId> {
+        interrupts::without_interrupts(|| self.ids.lock().pop_front())
+    }
+
+    fn is_empty(&self) -> bool {
+        interrupts::without_interrupts(|| self.ids.lock().is_empty())
+    }
+}
+
+struct TaskWaker {
+    id: TaskId,
+    queue: Arc<TaskQueue>,
+}
+
+impl TaskWaker {
+    fn waker(id: TaskId, queue: Arc<TaskQueue>) -> Waker {
+        Waker::from(Arc::new(TaskWaker { id, queue }))
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.queue.push(self.id);
+    }
+}
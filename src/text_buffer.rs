@@ -1,7 +1,13 @@
+use crate::sync::IrqSpinlock;
 use alloc::vec::Vec;
 use core::fmt;
 use lazy_static::lazy_static;
 
+/// The gap between the shell and the edges of the screen, in pixels.
+pub const SHELL_PADDING: u32 = 8;
+/// The gap between lines of the shell, in pixels.
+pub const SHELL_LINE_SPACING: u32 = 2;
+
 pub struct TextBuffer {
     pub width: usize,
     pub height: usize,
@@ -11,7 +17,7 @@ pub struct TextBuffer {
 }
 
 lazy_static! {
-    pub static ref SHELL: spin::Mutex<TextBuffer> = spin::Mutex::new(TextBuffer::empty());
+    pub static ref SHELL: IrqSpinlock<TextBuffer> = IrqSpinlock::new(TextBuffer::empty());
 }
 
 impl TextBuffer {
@@ -61,6 +67,7 @@ impl TextBuffer {
     pub fn delete(&mut self) {
         self.cursor -= 1;
         self.chars[self.cursor] = ' ';
+        self.dirty[self.cursor] = true;
     }
 
     pub fn newline(&mut self) {
@@ -92,6 +99,21 @@ impl TextBuffer {
             .map(|(i, _)| i)
     }
 
+    /// Moves the changed cells, with their positions, into `cells` and marks them clean. Stops
+    /// once `cells` is full rather than growing it, so it's safe to call with interrupts disabled;
+    /// whatever doesn't fit stays dirty for next time.
+    pub fn take_dirty(&mut self, cells: &mut Vec<(usize, char)>) {
+        for cursor in 0..self.capacity() {
+            if cells.len() == cells.capacity() {
+                return;
+            }
+            if self.dirty[cursor] {
+                cells.push((cursor, self.chars[cursor]));
+                self.dirty[cursor] = false;
+            }
+        }
+    }
+
     fn scroll_if_necessary(&mut self) {
         if self.cursor >= self.capacity() {
             self.scroll(2);
@@ -149,7 +171,5 @@ macro_rules! println {
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
 
-    x86_64::instructions::interrupts::without_interrupts(|| {
-        SHELL.lock().write_fmt(args).unwrap();
-    });
+    SHELL.lock().write_fmt(args).unwrap();
 }
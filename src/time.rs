@@ -0,0 +1,126 @@
+use crate::{acpi, mmio::Mmio, pit, serial_println};
+use core::{
+    arch::x86_64::_rdtsc,
+    ops::{Add, Sub},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+// how long to count tsc cycles for when calibrating
+const CALIBRATION_PERIOD: u64 = 50; // ms
+
+// hpet registers
+const HPET_CAPABILITIES: usize = 0x000;
+const HPET_CONFIGURATION: usize = 0x010;
+const HPET_MAIN_COUNTER: usize = 0x0F0;
+const HPET_ENABLE: u64 = 1;
+const HPET_64_BIT_COUNTER: u64 = 1 << 13;
+const FEMTOSECONDS_PER_MILLISECOND: u64 = 1_000_000_000_000;
+
+// the tsc when `init` was called, which timestamps count from
+static START_TSC: AtomicU64 = AtomicU64::new(0);
+// tsc cycles per second, or 0 while uncalibrated
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// A point in time, with nanosecond resolution, measured by the TSC since `init`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The current time. This is always zero until the TSC has been calibrated.
+    pub fn now() -> Self {
+        let frequency = TSC_FREQUENCY.load(Ordering::Relaxed);
+        if frequency == 0 {
+            return Self(0);
+        }
+
+        let cycles = tsc().saturating_sub(START_TSC.load(Ordering::Relaxed));
+        Self((cycles as u128 * 1_000_000_000 / frequency as u128) as u64)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now() - *self
+    }
+
+    /// The time between `init` and this instant.
+    pub fn since_start(&self) -> Duration {
+        Duration::from_nanos(self.0)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0 + duration.as_nanos() as u64)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    /// Saturates to zero if `earlier` is actually later.
+    fn sub(self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+}
+
+/// Marks the start of time, which should be as early in boot as possible.
+pub fn init() {
+    START_TSC.store(tsc(), Ordering::Relaxed);
+}
+
+/// Measures the TSC's frequency against the HPET if ACPI has found one, or the PIT otherwise.
+/// Until this is done, every `Instant` is zero.
+pub fn calibrate() {
+    let hpet = acpi::hpet_address().and_then(|address| Mmio::new(address, 0x100).ok());
+    let frequency = without_interrupts(|| {
+        hpet.and_then(|mut hpet| calibrate_with_hpet(&mut hpet))
+            .unwrap_or_else(calibrate_with_pit)
+    });
+    TSC_FREQUENCY.store(frequency, Ordering::Relaxed);
+    serial_println!("tsc runs at {} MHz", frequency / 1_000_000);
+}
+
+/// The TSC's frequency in Hz, if it has been calibrated.
+pub fn tsc_frequency() -> Option<u64> {
+    match TSC_FREQUENCY.load(Ordering::Relaxed) {
+        0 => None,
+        frequency => Some(frequency),
+    }
+}
+
+fn calibrate_with_pit() -> u64 {
+    let start = tsc();
+    pit::wait(CALIBRATION_PERIOD);
+    (tsc() - start) * 1000 / CALIBRATION_PERIOD
+}
+
+// gives up on hpets we can't use, i.e. ones with a nonsensical period or a 32 bit counter, which
+// could wrap during calibration
+fn calibrate_with_hpet(hpet: &mut Mmio) -> Option<u64> {
+    let capabilities = hpet.read::<u64>(HPET_CAPABILITIES);
+    // the counter period is in the top half of the capabilities, in femtoseconds
+    let period = capabilities >> 32;
+    if period == 0 || capabilities & HPET_64_BIT_COUNTER == 0 {
+        return None;
+    }
+
+    let configuration = hpet.read::<u64>(HPET_CONFIGURATION);
+    hpet.write(HPET_CONFIGURATION, configuration | HPET_ENABLE);
+
+    let counts = CALIBRATION_PERIOD * FEMTOSECONDS_PER_MILLISECOND / period;
+    let start_count = hpet.read::<u64>(HPET_MAIN_COUNTER);
+    let start = tsc();
+    while hpet
+        .read::<u64>(HPET_MAIN_COUNTER)
+        .wrapping_sub(start_count)
+        < counts
+    {}
+    Some((tsc() - start) * 1000 / CALIBRATION_PERIOD)
+}
+
+fn tsc() -> u64 {
+    unsafe { _rdtsc() }
+}
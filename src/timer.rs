@@ -0,0 +1,159 @@
+mod wheel;
+
+use crate::{
+    interrupts::{self, TIMER_IRQ},
+    pit,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use wheel::{Action, TimerWheel};
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// How often the timer interrupt fires, whether it comes from the PIT or the local APIC.
+pub const TICK_FREQUENCY: u32 = 1000; // Hz
+
+const NANOS_PER_TICK: u64 = 1_000_000_000 / TICK_FREQUENCY as u64;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static WHEEL: spin::Mutex<TimerWheel> = spin::Mutex::new(TimerWheel::new());
+
+/// Identifies a scheduled timer, so it can be cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerError {
+    /// Every slot in the timer table is in use.
+    TooManyTimers,
+}
+
+/// Starts the PIT ticking. If the APICs get enabled later on, the local APIC timer takes over at
+/// the same frequency.
+pub fn init() {
+    pit::start_periodic(TICK_FREQUENCY);
+    interrupts::register_irq(TIMER_IRQ, tick).expect("timer irq is taken");
+}
+
+fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+
+    // the wheel is unlocked while each action runs, so callbacks can schedule and cancel timers
+    loop {
+        let action = WHEEL.lock().expire(now);
+        match action {
+            Some(Action::Call(callback)) => callback(),
+            Some(Action::Wake(waker)) => waker.wake(),
+            None => break,
+        }
+    }
+}
+
+/// The number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// The time since the timer was started, to the nearest tick.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(ticks() * NANOS_PER_TICK)
+}
+
+/// Halts until at least `duration` has passed. Interrupts must be enabled, or nothing would wake
+/// us up.
+pub fn sleep(duration: Duration) {
+    assert!(
+        x86_64::instructions::interrupts::are_enabled(),
+        "sleeping with interrupts disabled"
+    );
+
+    let deadline = ticks() + duration_to_ticks(duration);
+    while ticks() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Calls `callback` from the timer interrupt once `delay` has passed.
+pub fn after(delay: Duration, callback: fn()) -> Result<TimerId, TimerError> {
+    schedule(
+        ticks() + duration_to_ticks(delay),
+        None,
+        Action::Call(callback),
+    )
+}
+
+/// Calls `callback` from the timer interrupt every `period`, starting one period from now, until
+/// the timer is cancelled.
+pub fn every(period: Duration, callback: fn()) -> Result<TimerId, TimerError> {
+    let period = duration_to_ticks(period);
+    schedule(ticks() + period, Some(period), Action::Call(callback))
+}
+
+/// Stops a timer, returning whether it hadn't expired yet. Periodic timers never expire.
+pub fn cancel(timer: TimerId) -> bool {
+    without_interrupts(|| WHEEL.lock().cancel(timer.0))
+}
+
+/// A future that completes once `duration` has passed, or fails if every timer is taken when it
+/// needs one to wake it.
+pub fn delay(duration: Duration) -> Delay {
+    Delay {
+        deadline: ticks() + duration_to_ticks(duration),
+        timer: None,
+        waker: None,
+    }
+}
+
+pub struct Delay {
+    deadline: u64,
+    // wakes whoever polled us last
+    timer: Option<TimerId>,
+    // a reference to the timer's waker, so the timer interrupt never drops the last one and
+    // frees it with the heap possibly locked
+    waker: Option<Waker>,
+}
+
+impl Future for Delay {
+    type Output = Result<(), TimerError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(timer) = self.timer.take() {
+            cancel(timer);
+        }
+        if ticks() >= self.deadline {
+            return Poll::Ready(Ok(()));
+        }
+
+        match schedule(self.deadline, None, Action::Wake(cx.waker().clone())) {
+            Ok(timer) => {
+                self.timer = Some(timer);
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Err(error) => Poll::Ready(Err(error)),
+        }
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            cancel(timer);
+        }
+    }
+}
+
+fn schedule(deadline: u64, period: Option<u64>, action: Action) -> Result<TimerId, TimerError> {
+    without_interrupts(|| WHEEL.lock().insert(deadline, period, action))
+        .map(TimerId)
+        .ok_or(TimerError::TooManyTimers)
+}
+
+/// The number of ticks in `duration`, rounded up so nothing ever happens early.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    (duration.as_nanos() as u64 + NANOS_PER_TICK - 1) / NANOS_PER_TICK
+}
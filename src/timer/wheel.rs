@@ -0,0 +1,153 @@
+use core::task::Waker;
+
+// one slot per tick, wrapping around. timers further out than this wait for the wheel to come
+// round again.
+const SLOTS: usize = 256;
+const MAX_TIMERS: usize = 128;
+
+/// What to do when a timer expires.
+#[derive(Debug, Clone)]
+pub enum Action {
+    Call(fn()),
+    Wake(Waker),
+}
+
+struct Timer {
+    id: u64,
+    deadline: u64,
+    period: Option<u64>,
+    action: Action,
+    // the next timer in the same slot
+    next: Option<usize>,
+}
+
+/// A hashed timing wheel, counting in ticks. Timers live in a fixed table, so that scheduling
+/// and expiring them never needs the heap.
+pub struct TimerWheel {
+    timers: [Option<Timer>; MAX_TIMERS],
+    // the first timer in each slot
+    slots: [Option<usize>; SLOTS],
+    // every tick before this one has been expired
+    current: u64,
+    next_id: u64,
+}
+
+impl TimerWheel {
+    pub const fn new() -> Self {
+        const EMPTY: Option<Timer> = None;
+        Self {
+            timers: [EMPTY; MAX_TIMERS],
+            slots: [None; SLOTS],
+            current: 0,
+            next_id: 0,
+        }
+    }
+
+    /// Schedules `action` for the given tick, and every `period` ticks after that if there is
+    /// one. Returns the timer's ID, or `None` if the table is full.
+    pub fn insert(&mut self, deadline: u64, period: Option<u64>, action: Action) -> Option<u64> {
+        let index = self.timers.iter().position(Option::is_none)?;
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.timers[index] = Some(Timer {
+            id,
+            // a deadline that has already passed expires on the next tick
+            deadline: deadline.max(self.current),
+            period: period.map(|period| period.max(1)),
+            action,
+            next: None,
+        });
+        self.link(index);
+        Some(id)
+    }
+
+    /// Removes a timer, returning whether it was still scheduled.
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let index = self
+            .timers
+            .iter()
+            .position(|timer| timer.as_ref().map(|timer| timer.id) == Some(id));
+        match index {
+            Some(index) => {
+                self.unlink(index);
+                self.timers[index] = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Takes the next timer that's due by tick `now`, in deadline order, and returns its action.
+    /// Periodic timers are rescheduled rather than removed.
+    pub fn expire(&mut self, now: u64) -> Option<Action> {
+        while self.current <= now {
+            let mut cursor = self.slots[self.current as usize % SLOTS];
+            while let Some(index) = cursor {
+                let timer = self.timers[index].as_mut().unwrap();
+                cursor = timer.next;
+                if timer.deadline > self.current {
+                    // due on a later trip round the wheel
+                    continue;
+                }
+
+                self.unlink(index);
+                let timer = self.timers[index].as_mut().unwrap();
+                let action = match timer.period {
+                    Some(period) => {
+                        timer.deadline += period;
+                        let action = timer.action.clone();
+                        self.link(index);
+                        action
+                    }
+                    None => self.timers[index].take().unwrap().action,
+                };
+                return Some(action);
+            }
+
+            self.current += 1;
+        }
+        None
+    }
+
+    // adds a timer to the end of its slot, so timers due on the same tick expire in the order
+    // they were scheduled
+    fn link(&mut self, index: usize) {
+        let slot = self.slot(index);
+        self.timers[index].as_mut().unwrap().next = None;
+
+        match self.slots[slot] {
+            None => self.slots[slot] = Some(index),
+            Some(mut last) => {
+                while let Some(next) = self.timer(last).next {
+                    last = next;
+                }
+                self.timers[last].as_mut().unwrap().next = Some(index);
+            }
+        }
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let slot = self.slot(index);
+        let next = self.timer(index).next;
+
+        if self.slots[slot] == Some(index) {
+            self.slots[slot] = next;
+            return;
+        }
+
+        let mut previous = self.slots[slot].unwrap();
+        while self.timer(previous).next != Some(index) {
+            previous = self.timer(previous).next.unwrap();
+        }
+        self.timers[previous].as_mut().unwrap().next = next;
+    }
+
+    fn slot(&self, index: usize) -> usize {
+        self.timer(index).deadline as usize % SLOTS
+    }
+
+    fn timer(&self, index: usize) -> &Timer {
+        self.timers[index].as_ref().unwrap()
+    }
+}
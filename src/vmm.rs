@@ -0,0 +1,335 @@
+use crate::memory::{self, BitmapFrameAllocator};
+use alloc::vec::Vec;
+use core::ops::Range;
+use x86_64::{
+    align_down, align_up,
+    structures::paging::{
+        mapper::{FlagUpdateError, MapToError, MappedFrame, TranslateResult, UnmapError},
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags,
+        PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// The kernel's virtual memory manager. It lives in a static rather than on `Kernel` directly so
+/// that the heap can grow itself while the kernel is locked.
+pub static VMM: VirtualMemoryManager = VirtualMemoryManager::new();
+
+const REGION_SIZE: u64 = 64 * 1024 * 1024 * 1024; // 64 GiB
+
+/// A window of kernel address space that is handed out in non-overlapping pieces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Heap,
+    Stacks,
+    Mmio,
+    Framebuffer,
+}
+
+impl Region {
+    const ALL: [Region; 4] = [
+        Region::Heap,
+        Region::Stacks,
+        Region::Mmio,
+        Region::Framebuffer,
+    ];
+
+    pub fn start(self) -> VirtAddr {
+        VirtAddr::new(match self {
+            Region::Heap => crate::allocator::HEAP_START as u64,
+            Region::Stacks => 0x5555_0000_0000,
+            Region::Mmio => 0x6666_0000_0000,
+            Region::Framebuffer => 0x7777_0000_0000,
+        })
+    }
+
+    pub fn end(self) -> VirtAddr {
+        self.start() + REGION_SIZE
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+pub struct VirtualMemoryManager {
+    inner: spin::Mutex<Option<Inner>>,
+}
+
+struct Inner {
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BitmapFrameAllocator,
+    // the next unreserved address in each region
+    next: [VirtAddr; Region::ALL.len()],
+}
+
+impl VirtualMemoryManager {
+    const fn new() -> Self {
+        Self {
+            inner: spin::Mutex::new(None),
+        }
+    }
+
+    /// Takes ownership of the kernel's page table mapper and frame allocator.
+    pub fn init(&self, mapper: OffsetPageTable<'static>, frame_allocator: BitmapFrameAllocator) {
+        *self.inner.lock() = Some(Inner {
+            mapper,
+            frame_allocator,
+            next: Region::ALL.map(Region::start),
+        });
+    }
+
+    /// Reserves `size` bytes (rounded up to whole pages) of address space in the given region.
+    /// Reserved ranges never overlap, and are never given back.
+    pub fn reserve(&self, region: Region, size: usize) -> Option<VirtAddr> {
+        self.with(|inner| {
+            let size = align_up(size as u64, Size4KiB::SIZE);
+            let start = inner.next[region.index()];
+            if region.end() - start < size {
+                return None;
+            }
+
+            inner.next[region.index()] = start + size;
+            Some(start)
+        })
+    }
+
+    /// Backs the pages covering the given range with fresh frames. If that fails partway, the
+    /// pages mapped so far are unmapped again, so the range can be retried.
+    pub fn map(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        self.with(|inner| {
+            for (mapped, page) in pages(start, size).enumerate() {
+                if let Err(error) = inner.map_fresh_frame(page, flags) {
+                    for page in pages(start, size).take(mapped) {
+                        let (frame, flush) = inner
+                            .mapper
+                            .unmap(page)
+                            .expect("page we just mapped is gone");
+                        flush.flush();
+                        unsafe {
+                            inner.frame_allocator.deallocate_frame(frame);
+                        }
+                    }
+                    return Err(error);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Maps the pages covering the given range onto the physical range starting at `physical`.
+    /// The frames are not taken from the frame allocator, so this is for memory we don't own,
+    /// such as device registers.
+    pub fn map_physical(
+        &self,
+        start: VirtAddr,
+        physical: PhysAddr,
+        size: usize,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        self.with(|inner| {
+            let frames = PhysFrame::range(
+                PhysFrame::containing_address(physical),
+                PhysFrame::containing_address((physical + size).align_up(Size4KiB::SIZE)),
+            );
+            for (page, frame) in pages(start, size).zip(frames) {
+                unsafe {
+                    inner
+                        .mapper
+                        .map_to(page, frame, flags, &mut inner.frame_allocator)?
+                        .flush();
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Unmaps a range mapped with `map_physical`, leaving the frames alone.
+    pub fn unmap_physical(&self, start: VirtAddr, size: usize) -> Result<(), UnmapError> {
+        self.with(|inner| {
+            for page in pages(start, size) {
+                inner.mapper.unmap(page)?.1.flush();
+            }
+            Ok(())
+        })
+    }
+
+    /// Unmaps the pages covering the given range and frees their frames.
+    pub fn unmap(&self, start: VirtAddr, size: usize) -> Result<(), UnmapError> {
+        self.with(|inner| {
+            for page in pages(start, size) {
+                let (frame, flush) = inner.mapper.unmap(page)?;
+                flush.flush();
+                unsafe {
+                    inner.frame_allocator.deallocate_frame(frame);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Replaces the flags on the pages covering the given range.
+    pub fn protect(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: PageTableFlags,
+    ) -> Result<(), FlagUpdateError> {
+        self.with(|inner| {
+            for page in pages(start, size) {
+                unsafe {
+                    inner.mapper.update_flags(page, flags)?.flush();
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Like `protect`, but works on huge pages too, and computes each page's new flags from its
+    /// current ones.
+    pub fn update_flags(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        f: impl Fn(PageTableFlags) -> PageTableFlags,
+    ) -> Result<(), FlagUpdateError> {
+        self.with(|inner| {
+            let end = start + size;
+            let mut address = start.align_down(Size4KiB::SIZE);
+            while address < end {
+                let (frame, flags) = match inner.mapper.translate(address) {
+                    TranslateResult::Mapped { frame, flags, .. } => (frame, f(flags)),
+                    _ => return Err(FlagUpdateError::PageNotMapped),
+                };
+
+                unsafe {
+                    match frame {
+                        MappedFrame::Size4KiB(_) => {
+                            let page = Page::<Size4KiB>::containing_address(address);
+                            inner.mapper.update_flags(page, flags)?.flush();
+                        }
+                        MappedFrame::Size2MiB(_) => {
+                            let page = Page::<Size2MiB>::containing_address(address);
+                            inner.mapper.update_flags(page, flags)?.flush();
+                        }
+                        MappedFrame::Size1GiB(_) => {
+                            let page = Page::<Size1GiB>::containing_address(address);
+                            inner.mapper.update_flags(page, flags)?.flush();
+                        }
+                    }
+                }
+                address = (address + 1u64).align_up(frame.size());
+            }
+            Ok(())
+        })
+    }
+
+    /// Makes sure no page is both writable and executable. The kernel's code and read-only data
+    /// are made read-only, and everything else that's writable (data, stacks, the heap, physical
+    /// memory) is made non-executable. Needs `memory::enable_nx` to have been called first.
+    pub fn enforce_write_xor_execute(&self) -> Result<(), FlagUpdateError> {
+        let image = kernel_read_only_image();
+
+        // collect the ranges before changing any of them, with the room reserved up front so
+        // the heap doesn't grow and change the tables during the walk either
+        let mut count = 0;
+        memory::for_each_mapped_range(|_| count += 1);
+        let mut ranges = Vec::with_capacity(count + 16);
+        memory::for_each_mapped_range(|range| ranges.push(range));
+
+        for range in ranges {
+            let (start, end) = (range.start.as_u64(), range.end());
+
+            let (read_only_start, read_only_end) = (start.max(image.start), end.min(image.end));
+            if read_only_start < read_only_end {
+                self.update_flags(
+                    VirtAddr::new(read_only_start),
+                    (read_only_end - read_only_start) as usize,
+                    |flags| flags - PageTableFlags::WRITABLE,
+                )?;
+            }
+
+            if range.flags.contains(PageTableFlags::WRITABLE)
+                && !range.flags.contains(PageTableFlags::NO_EXECUTE)
+            {
+                // whatever's left on either side of the kernel's image
+                for (start, end) in [(start, end.min(image.start)), (start.max(image.end), end)] {
+                    if start < end {
+                        self.update_flags(VirtAddr::new(start), (end - start) as usize, |flags| {
+                            flags | PageTableFlags::NO_EXECUTE
+                        })?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the given function with the frame allocator, for code that needs physical frames
+    /// directly.
+    pub fn with_frame_allocator<T>(&self, f: impl FnOnce(&mut BitmapFrameAllocator) -> T) -> T {
+        self.with(|inner| f(&mut inner.frame_allocator))
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Inner) -> T) -> T {
+        let mut inner = self.inner.lock();
+        f(inner
+            .as_mut()
+            .expect("virtual memory manager used before initialisation"))
+    }
+}
+
+impl Inner {
+    fn map_fresh_frame(
+        &mut self,
+        page: Page<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let frame = self
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe {
+            match self
+                .mapper
+                .map_to(page, frame, flags, &mut self.frame_allocator)
+            {
+                Ok(flush) => flush.flush(),
+                Err(error) => {
+                    self.frame_allocator.deallocate_frame(frame);
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+extern "C" {
+    // defined by the linker, which puts the headers and read-only data first, then the text
+    static __executable_start: u8;
+    static _etext: u8;
+}
+
+// the part of the kernel's image that's read-only: its headers, read-only data and code
+fn kernel_read_only_image() -> Range<u64> {
+    let (start, end) = unsafe {
+        (
+            &__executable_start as *const u8 as u64,
+            &_etext as *const u8 as u64,
+        )
+    };
+    align_down(start, Size4KiB::SIZE)..align_up(end, Size4KiB::SIZE)
+}
+
+// all pages covering the given range
+fn pages(start: VirtAddr, size: usize) -> impl Iterator<Item = Page<Size4KiB>> {
+    let first_page = Page::containing_address(start);
+    let end_page = Page::containing_address((start + size).align_up(Size4KiB::SIZE));
+    Page::range(first_page, end_page)
+}
@@ -0,0 +1,162 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc};
+use bootloader::{entry_point, BootInfo};
+use core::{alloc::Layout, arch::x86_64::_rdtsc, panic::PanicInfo, ptr::NonNull};
+use feebos::{halt_loop, kernel::k, serial_print};
+use linked_list_allocator::Heap;
+
+const ITERATIONS: usize = 10_000;
+const LIVE_ALLOCATIONS: usize = 64;
+const ALLOCATION_SIZES: [usize; 8] = [8, 24, 64, 200, 512, 1000, 2048, 4096];
+const COMPARISON_HEAP_SIZE: usize = 1024 * 1024;
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+// both heaps are driven through this so they run exactly the same workload
+trait TestHeap {
+    fn allocate(&mut self, layout: Layout) -> *mut u8;
+    fn free(&mut self, ptr: *mut u8, layout: Layout);
+}
+
+// the kernel's global allocator
+struct KernelHeap;
+
+impl TestHeap for KernelHeap {
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { alloc(layout) };
+        assert!(!ptr.is_null());
+        ptr
+    }
+
+    fn free(&mut self, ptr: *mut u8, layout: Layout) {
+        unsafe { dealloc(ptr, layout) }
+    }
+}
+
+// the linked list heap we used before the slab allocator
+impl TestHeap for Heap {
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        self.allocate_first_fit(layout).unwrap().as_ptr()
+    }
+
+    fn free(&mut self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.deallocate(NonNull::new(ptr).unwrap(), layout) }
+    }
+}
+
+fn linked_list_heap() -> Heap {
+    let layout = Layout::from_size_align(COMPARISON_HEAP_SIZE, 4096).unwrap();
+    let memory = KernelHeap.allocate(layout);
+    unsafe { Heap::new(memory as usize, COMPARISON_HEAP_SIZE) }
+}
+
+// allocate and immediately free lots of small values, like the many_boxes heap test
+fn boxes(heap: &mut impl TestHeap) {
+    let layout = Layout::new::<usize>();
+    for i in 0..ITERATIONS {
+        let ptr = heap.allocate(layout) as *mut usize;
+        unsafe {
+            ptr.write(i);
+            assert_eq!(ptr.read(), i);
+        }
+        heap.free(ptr as *mut u8, layout);
+    }
+}
+
+// keep a set of differently sized allocations alive, replacing one of them each iteration.
+// every allocation is filled with a pattern that is checked before it is freed.
+fn mixed(heap: &mut impl TestHeap) {
+    let mut live: [Option<(*mut u8, Layout)>; LIVE_ALLOCATIONS] = [None; LIVE_ALLOCATIONS];
+    for i in 0..ITERATIONS {
+        let slot = (i * 7) % LIVE_ALLOCATIONS;
+        if let Some((ptr, layout)) = live[slot].take() {
+            check_and_free(heap, ptr, layout);
+        }
+
+        let size = ALLOCATION_SIZES[(i * 31) % ALLOCATION_SIZES.len()];
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let ptr = heap.allocate(layout);
+        unsafe { ptr.write_bytes(pattern(ptr), size) };
+        live[slot] = Some((ptr, layout));
+    }
+
+    for (ptr, layout) in live.iter().flatten() {
+        check_and_free(heap, *ptr, *layout);
+    }
+}
+
+fn pattern(ptr: *mut u8) -> u8 {
+    (ptr as usize >> 3) as u8
+}
+
+fn check_and_free(heap: &mut impl TestHeap, ptr: *mut u8, layout: Layout) {
+    let contents = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+    assert!(contents.iter().all(|&byte| byte == pattern(ptr)));
+    heap.free(ptr, layout);
+}
+
+fn cycles(workload: impl FnOnce()) -> u64 {
+    let start = unsafe { _rdtsc() };
+    workload();
+    unsafe { _rdtsc() - start }
+}
+
+fn compare(workload: fn(&mut KernelHeap), comparison: fn(&mut Heap)) {
+    let mut linked_list = linked_list_heap();
+    let kernel_cycles = cycles(|| workload(&mut KernelHeap));
+    let linked_list_cycles = cycles(|| comparison(&mut linked_list));
+    serial_print!(
+        "slab {} vs linked list {} cycles ",
+        kernel_cycles,
+        linked_list_cycles
+    );
+}
+
+#[test_case]
+fn boxes_benchmark() {
+    compare(boxes, boxes);
+}
+
+#[test_case]
+fn mixed_benchmark() {
+    compare(mixed, mixed);
+}
+
+#[test_case]
+fn every_size_class() {
+    for size in 1..=4096 {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        let ptr = KernelHeap.allocate(layout);
+        unsafe { ptr.write_bytes(0xAA, size) };
+        KernelHeap.free(ptr, layout);
+    }
+}
+
+#[test_case]
+fn aligned_allocations() {
+    for shift in 0..=12 {
+        let align = 1 << shift;
+        let layout = Layout::from_size_align(8, align).unwrap();
+        let ptr = KernelHeap.allocate(layout);
+        assert_eq!(ptr as usize % align, 0);
+        KernelHeap.free(ptr, layout);
+    }
+}
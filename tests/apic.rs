@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use feebos::{acpi, apic, halt_loop, kernel::k};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_describes_qemu() {
+    let madt = acpi::madt().expect("no madt found");
+    assert!(!madt.processors.is_empty());
+    assert_eq!(madt.io_apics.len(), 1);
+
+    // qemu moves the pit from irq 0 to global system interrupt 2
+    let timer = madt.overrides.iter().find(|o| o.irq == 0).unwrap();
+    assert_eq!(timer.gsi, 2);
+}
+
+#[test_case]
+fn apic_is_enabled() {
+    assert!(apic::is_enabled());
+    let id = apic::local_apic_id().unwrap();
+    let processors = &acpi::madt().unwrap().processors;
+    assert!(processors.iter().any(|processor| processor.apic_id == id));
+}
+
+#[test_case]
+fn timer_interrupts_arrive() {
+    // nothing else interrupts us, so this only returns if the local apic timer is running
+    for _ in 0..10 {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use bootloader::{entry_point, BootInfo};
+use feebos::{
+    exit_qemu, kernel::k, serial_print, serial_println, serial_writer::SERIAL, QemuExitCode,
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+static FINISHED: AtomicUsize = AtomicUsize::new(0);
+static DOUBLE_PANIC_REPORTED: AtomicBool = AtomicBool::new(false);
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    serial_print!("{:.<76}", "double_panic::stops_at_the_second_panic");
+    feebos::panic::set_finish(finish);
+    without_interrupts(|| SERIAL.lock().set_tee(Some(watch_for_double_panic)));
+
+    panic!("first panic");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::panic::handle(info)
+}
+
+fn watch_for_double_panic(written: &str) {
+    if written.contains("double panic") {
+        DOUBLE_PANIC_REPORTED.store(true, Ordering::SeqCst);
+    }
+}
+
+// where the kernel's panic handler ends up, instead of halting
+fn finish() -> ! {
+    match FINISHED.fetch_add(1, Ordering::SeqCst) {
+        // panic again from inside the handler, which should be reported without starting over
+        0 => panic!("second panic"),
+        1 if DOUBLE_PANIC_REPORTED.load(Ordering::SeqCst) => {
+            SERIAL.lock().set_tee(None);
+            serial_println!("[ok]");
+            exit_qemu(QemuExitCode::Success);
+        }
+        _ => {
+            SERIAL.lock().set_tee(None);
+            serial_println!("[failed]");
+            serial_println!("the second panic wasn't reported as a double panic");
+            exit_qemu(QemuExitCode::Failed);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use feebos::{halt_loop, kernel::k, vmm::VMM};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+#[test_case]
+fn allocate_and_free() {
+    VMM.with_frame_allocator(|frame_allocator| {
+        let free_before = frame_allocator.free_frames();
+
+        let frame = frame_allocator.allocate_frame().unwrap();
+        assert_eq!(frame_allocator.free_frames(), free_before - 1);
+
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        assert_eq!(frame_allocator.free_frames(), free_before);
+    });
+}
+
+#[test_case]
+fn freed_frames_are_reused() {
+    VMM.with_frame_allocator(|frame_allocator| {
+        let frame = frame_allocator.allocate_frame().unwrap();
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        assert_eq!(frame_allocator.allocate_frame(), Some(frame));
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    });
+}
+
+#[test_case]
+fn many_frames() {
+    // reserve up front, the heap needs the frame allocator if it has to grow
+    let mut frames = Vec::with_capacity(1000);
+
+    VMM.with_frame_allocator(|frame_allocator| {
+        let free_before = frame_allocator.free_frames();
+
+        for _ in 0..frames.capacity() {
+            frames.push(frame_allocator.allocate_frame().unwrap());
+        }
+        assert_eq!(frame_allocator.free_frames(), free_before - frames.len());
+        // sorting doesn't allocate, which matters while the frame allocator is locked
+        frames.sort_unstable();
+        assert!(frames.windows(2).all(|pair| pair[0] != pair[1]));
+
+        for frame in frames.drain(..) {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+        assert_eq!(frame_allocator.free_frames(), free_before);
+    });
+}
+
+#[test_case]
+fn low_frames_are_counted_separately() {
+    VMM.with_frame_allocator(|frame_allocator| {
+        let (free_before, free_low_before) = (
+            frame_allocator.free_frames(),
+            frame_allocator.free_low_frames(),
+        );
+
+        let frame = frame_allocator.allocate_low_frame().unwrap();
+        assert!(frame.start_address().as_u64() < 0x10_0000);
+        assert_eq!(frame_allocator.free_frames(), free_before);
+        assert_eq!(frame_allocator.free_low_frames(), free_low_before - 1);
+
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        assert_eq!(frame_allocator.free_low_frames(), free_low_before);
+    });
+}
@@ -6,10 +6,16 @@
 
 extern crate alloc;
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use feebos::{allocator::HEAP_SIZE, halt_loop, kernel::k};
+use feebos::{
+    allocator::{self, HEAP_MAX_SIZE, HEAP_SIZE},
+    halt_loop,
+    kernel::k,
+    vmm::VMM,
+};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
 
 entry_point!(test_entry_point);
 
@@ -49,3 +55,97 @@ fn many_boxes() {
         assert_eq!(*x, i);
     }
 }
+
+#[test_case]
+fn stats_follow_allocations() {
+    let before = allocator::stats();
+    let value = Box::new([0u8; 100]);
+    let during = allocator::stats();
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert_eq!(during.bytes_allocated, before.bytes_allocated + 100);
+    assert!(during.peak_bytes_allocated >= during.bytes_allocated);
+
+    drop(value);
+    assert_eq!(allocator::stats().bytes_allocated, before.bytes_allocated);
+}
+
+#[test_case]
+fn no_leaks() {
+    let leaks = allocator::track_leaks(|| {
+        let vec: Vec<u64> = (0..100).collect();
+        let value = Box::new(vec.len());
+        assert_eq!(*value, 100);
+    });
+    assert!(leaks.is_empty(), "leaked {}", leaks[0]);
+}
+
+#[test_case]
+fn leaks_are_reported() {
+    let leaks = allocator::track_leaks(|| {
+        core::mem::forget(Box::new(42u64));
+    });
+    assert_eq!(leaks.len(), 1);
+    assert_eq!(leaks[0].size, 8);
+    assert_ne!(leaks[0].caller, 0);
+}
+
+#[test_case]
+fn freed_memory_is_poisoned() {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let value = Box::new([0x42u8; 64]);
+    let ptr = value.as_ptr();
+    drop(value);
+
+    let freed = unsafe { core::slice::from_raw_parts(ptr, 64) };
+    assert!(freed.iter().all(|&byte| byte == allocator::POISON));
+}
+
+#[test_case]
+fn heap_grows() {
+    let big = vec![1u8; HEAP_SIZE * 4];
+    assert_eq!(
+        big.iter().map(|&b| b as usize).sum::<usize>(),
+        HEAP_SIZE * 4
+    );
+}
+
+#[test_case]
+fn heap_limit() {
+    let mut vec = Vec::<u8>::new();
+    assert!(vec.try_reserve_exact(HEAP_MAX_SIZE).is_err());
+}
+
+#[test_case]
+fn failed_growth_gives_frames_back() {
+    const FRAMES_LEFT: usize = 8;
+    let free_frames = || VMM.with_frame_allocator(|frame_allocator| frame_allocator.free_frames());
+
+    // take all but a few frames, so growing the heap runs out partway through mapping. the room
+    // is reserved first, since the heap can't grow while the frame allocator is locked.
+    let mut taken = Vec::with_capacity(free_frames());
+    VMM.with_frame_allocator(|frame_allocator| {
+        while frame_allocator.free_frames() > FRAMES_LEFT {
+            assert!(taken.len() < taken.capacity());
+            taken.push(frame_allocator.allocate_frame().unwrap());
+        }
+    });
+
+    // more pages than there are frames left, but well within the heap's limit
+    let grow = || Vec::<u8>::new().try_reserve_exact(2 * 1024 * 1024).is_err();
+    // the first failure may leave new page tables behind, which belong to the address space
+    // rather than the heap, so compare around the second
+    assert!(grow());
+    let before = free_frames();
+    assert!(before > 0);
+    assert!(grow());
+    assert_eq!(free_frames(), before);
+
+    VMM.with_frame_allocator(|frame_allocator| {
+        for frame in taken.drain(..) {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+    });
+}
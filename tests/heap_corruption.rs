@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::{
+    fmt::{self, Write},
+    panic::PanicInfo,
+};
+use feebos::{exit_qemu, kernel::k, serial_print, serial_println, QemuExitCode};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    serial_print!("{:.<76}", "heap_corruption::overflow_is_caught");
+    if !cfg!(debug_assertions) {
+        serial_println!("[skipped, red zones are only checked in debug builds]");
+        exit_qemu(QemuExitCode::Success);
+    }
+
+    overflow_is_caught();
+    serial_println!("[corruption went unnoticed]");
+    exit_qemu(QemuExitCode::Failed);
+}
+
+fn overflow_is_caught() {
+    let mut buffer = Box::new([0u8; 16]);
+    unsafe {
+        // write one byte past the end of the allocation
+        buffer.as_mut_ptr().add(16).write_volatile(0xFF);
+    }
+    drop(buffer);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // any other panic, like running out of memory, doesn't count
+    let mut message = Message::new();
+    let _ = write!(message, "{}", info);
+    if !message.as_str().contains("red zone after allocation") {
+        serial_println!("[failed]");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+}
+
+// the start of a panic message, formatted without touching the heap, which might be the reason
+// we're panicking
+struct Message {
+    buffer: [u8; 256],
+    len: usize,
+}
+
+impl Message {
+    fn new() -> Self {
+        Self {
+            buffer: [0; 256],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // whatever doesn't fit is dropped
+        let len = s.len().min(self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::{
+    arch::asm,
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use feebos::{
+    halt_loop,
+    interrupts::{self, IrqError, IRQ_COUNT, KEYBOARD_IRQ, TIMER_IRQ},
+    kernel::k,
+};
+
+// nothing is attached to this line in qemu, and its vector is 32 + 5
+const TEST_IRQ: u8 = 5;
+
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+fn test_handler() {
+    CALLS.fetch_add(1, Ordering::SeqCst);
+}
+
+fn raise_test_irq() {
+    unsafe { asm!("int 37") };
+}
+
+#[test_case]
+fn registered_handler_is_called() {
+    interrupts::register_irq(TEST_IRQ, test_handler).unwrap();
+    let calls = CALLS.load(Ordering::SeqCst);
+    let count = interrupts::irq_count(TEST_IRQ).unwrap();
+
+    raise_test_irq();
+    assert_eq!(CALLS.load(Ordering::SeqCst), calls + 1);
+    assert_eq!(interrupts::irq_count(TEST_IRQ).unwrap(), count + 1);
+
+    assert!(interrupts::unregister_irq(TEST_IRQ).is_some());
+}
+
+#[test_case]
+fn unregistered_handler_is_not_called() {
+    interrupts::register_irq(TEST_IRQ, test_handler).unwrap();
+    interrupts::unregister_irq(TEST_IRQ).unwrap();
+    let calls = CALLS.load(Ordering::SeqCst);
+    let count = interrupts::irq_count(TEST_IRQ).unwrap();
+
+    raise_test_irq();
+    assert_eq!(CALLS.load(Ordering::SeqCst), calls);
+    assert_eq!(interrupts::irq_count(TEST_IRQ).unwrap(), count + 1);
+    assert!(interrupts::unregister_irq(TEST_IRQ).is_none());
+}
+
+#[test_case]
+fn one_handler_per_irq() {
+    assert_eq!(
+        interrupts::register_irq(KEYBOARD_IRQ, test_handler),
+        Err(IrqError::AlreadyRegistered(KEYBOARD_IRQ))
+    );
+    assert_eq!(
+        interrupts::register_irq(IRQ_COUNT as u8, test_handler),
+        Err(IrqError::InvalidIrq(IRQ_COUNT as u8))
+    );
+    assert_eq!(interrupts::irq_count(IRQ_COUNT as u8), None);
+}
+
+#[test_case]
+fn timer_is_counted() {
+    let ticks = interrupts::irq_count(TIMER_IRQ).unwrap();
+    x86_64::instructions::hlt();
+    assert!(interrupts::irq_count(TIMER_IRQ).unwrap() > ticks);
+}
@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::{arch::asm, fmt::Write, panic::PanicInfo};
+use feebos::{
+    exit_qemu,
+    kernel::k,
+    serial_print, serial_println,
+    stack::{KernelStack, DEFAULT_STACK_SIZE},
+    QemuExitCode,
+};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    serial_print!("{:.<76}", "kernel_stack_overflow::guard_page_is_reported");
+
+    // make a couple of stacks first, so the one that overflows isn't simply stack 0
+    let _first = KernelStack::new(DEFAULT_STACK_SIZE).unwrap();
+    let _second = KernelStack::new(DEFAULT_STACK_SIZE).unwrap();
+    let stack = KernelStack::new(DEFAULT_STACK_SIZE).unwrap();
+    unsafe {
+        EXPECTED_STACK = stack.id();
+        asm!(
+            "mov rsp, {stack}",
+            "call {function}",
+            stack = in(reg) stack.top().as_u64(),
+            function = in(reg) stack_overflow as extern "C" fn() as usize,
+            options(noreturn),
+        );
+    }
+}
+
+static mut EXPECTED_STACK: usize = 0;
+
+#[allow(unconditional_recursion)]
+extern "C" fn stack_overflow() {
+    stack_overflow();
+    let v = 0;
+    volatile::Volatile::new(&v).read();
+}
+
+// just enough space to format the panic message into
+struct Message {
+    buffer: [u8; 512],
+    len: usize,
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = (self.len + s.len()).min(self.buffer.len());
+        self.buffer[self.len..end].copy_from_slice(&s.as_bytes()[..end - self.len]);
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Message {
+        buffer: [0; 512],
+        len: 0,
+    };
+    let _ = write!(message, "{}", info);
+
+    let mut expected = Message {
+        buffer: [0; 512],
+        len: 0,
+    };
+    let _ = writeln!(expected, "stack overflow in stack {}", unsafe {
+        EXPECTED_STACK
+    });
+
+    let expected = &expected.buffer[..expected.len];
+    if message.buffer[..message.len]
+        .windows(expected.len())
+        .any(|window| window == expected)
+    {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    }
+
+    serial_println!("[failed]");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+}
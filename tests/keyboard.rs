@@ -0,0 +1,143 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use feebos::{
+    halt_loop,
+    kernel::k,
+    keyboard::{self, KeyboardConfig, Layout, ScancodeQueue, ScancodeSet},
+    shell,
+};
+use pc_keyboard::{DecodedKey, HandleControl};
+
+// scan code set 1
+const SHIFT_DOWN: u8 = 0x2A;
+const SHIFT_UP: u8 = 0xAA;
+const A_DOWN: u8 = 0x1E;
+const A_UP: u8 = 0x9E;
+const TWO_DOWN: u8 = 0x03;
+const TWO_UP: u8 = 0x83;
+const Q_DOWN: u8 = 0x10;
+const Q_UP: u8 = 0x90;
+
+// scan code set 2
+const SET2_A: u8 = 0x1C;
+const SET2_RELEASE: u8 = 0xF0;
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+#[test_case]
+fn queue_is_first_in_first_out() {
+    let queue = ScancodeQueue::new();
+    assert!(queue.is_empty());
+    for scancode in 0..100 {
+        assert!(queue.push(scancode));
+    }
+    for scancode in 0..100 {
+        assert_eq!(queue.pop(), Some(scancode));
+    }
+    assert_eq!(queue.pop(), None);
+}
+
+#[test_case]
+fn queue_counts_overflows() {
+    let queue = ScancodeQueue::new();
+    let mut pushed = 0;
+    while queue.push(pushed) {
+        pushed += 1;
+    }
+    assert!(!queue.push(0));
+    assert_eq!(queue.overflows(), 2);
+
+    // everything that fit is still there
+    for scancode in 0..pushed {
+        assert_eq!(queue.pop(), Some(scancode));
+    }
+}
+
+#[test_case]
+fn decodes_keys_with_modifiers() {
+    assert_eq!(keyboard::try_read_key(), None);
+
+    for scancode in [A_DOWN, A_UP, SHIFT_DOWN, A_DOWN, A_UP, SHIFT_UP] {
+        assert!(keyboard::inject_scancode(scancode));
+    }
+
+    let key = keyboard::read_key();
+    assert_eq!(key.key, DecodedKey::Unicode('a'));
+    assert!(!key.modifiers.shift);
+
+    let key = keyboard::read_key();
+    assert_eq!(key.key, DecodedKey::Unicode('A'));
+    assert!(key.modifiers.shift);
+
+    assert_eq!(keyboard::try_read_key(), None);
+}
+
+fn type_keys(scancodes: &[u8]) -> impl Iterator<Item = DecodedKey> + '_ {
+    scancodes
+        .iter()
+        .filter_map(|&scancode| keyboard::decode(scancode))
+        .map(|press| press.key)
+}
+
+#[test_case]
+fn layouts_switch_at_runtime() {
+    let shifted_two = [SHIFT_DOWN, TWO_DOWN, TWO_UP, SHIFT_UP];
+
+    keyboard::configure(KeyboardConfig::default()).unwrap();
+    assert!(type_keys(&shifted_two).eq([DecodedKey::Unicode('@')]));
+
+    shell::run_command("layout uk");
+    assert_eq!(keyboard::config().layout, Layout::Uk105);
+    assert!(type_keys(&shifted_two).eq([DecodedKey::Unicode('"')]));
+
+    shell::run_command("layout dvorak");
+    assert_eq!(keyboard::config().layout, Layout::Dvorak104);
+    assert!(type_keys(&[Q_DOWN, Q_UP]).eq([DecodedKey::Unicode('\'')]));
+
+    keyboard::configure(KeyboardConfig::default()).unwrap();
+}
+
+#[test_case]
+fn scancode_sets_switch_at_runtime() {
+    shell::run_command("scancodes 2");
+    shell::run_command("ctrl unicode");
+    let config = keyboard::config();
+    assert_eq!(config.scancode_set, ScancodeSet::Set2);
+    assert_eq!(config.handle_control, HandleControl::MapLettersToUnicode);
+    assert_eq!(config.layout, Layout::Us104);
+
+    assert!(type_keys(&[SET2_A, SET2_RELEASE, SET2_A]).eq([DecodedKey::Unicode('a')]));
+
+    keyboard::configure(KeyboardConfig::default()).unwrap();
+}
+
+#[test_case]
+fn cmdline_picks_the_config() {
+    let config =
+        KeyboardConfig::default().with_cmdline("quiet keyboard.layout=dvorak keyboard.scancodes=2");
+    assert_eq!(config.layout, Layout::Dvorak104);
+    assert_eq!(config.scancode_set, ScancodeSet::Set2);
+    assert_eq!(config.handle_control, HandleControl::Ignore);
+
+    // nonsense is ignored rather than taken as the default
+    let config = config.with_cmdline("keyboard.layout=qwertz keyboard.ctrl=unicode");
+    assert_eq!(config.layout, Layout::Dvorak104);
+    assert_eq!(config.handle_control, HandleControl::MapLettersToUnicode);
+}
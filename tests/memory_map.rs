@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use feebos::{
+    allocator::HEAP_START,
+    halt_loop,
+    kernel::k,
+    memory::{self, MappedRange},
+};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+fn mapped_ranges() -> Vec<MappedRange> {
+    // collect into a vec with room to spare, so the heap doesn't grow while we walk the tables
+    let mut ranges = Vec::with_capacity(4096);
+    memory::for_each_mapped_range(|range| {
+        assert!(ranges.len() < ranges.capacity());
+        ranges.push(range);
+    });
+    ranges
+}
+
+#[test_case]
+fn ranges_are_sorted_and_merged() {
+    let ranges = mapped_ranges();
+    assert!(!ranges.is_empty());
+    for pair in ranges.windows(2) {
+        assert!(pair[0].end() <= pair[1].start.as_u64());
+        assert!(
+            pair[0].end() != pair[1].start.as_u64()
+                || pair[0].physical + pair[0].size != pair[1].physical
+                || pair[0].flags != pair[1].flags
+        );
+    }
+}
+
+#[test_case]
+fn heap_is_mapped_writable_and_not_executable() {
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    let heap = mapped_ranges()
+        .into_iter()
+        .find(|range| range.contains(heap_start))
+        .expect("heap is not mapped");
+    assert!(heap
+        .flags
+        .contains(PageTableFlags::PRESENT | PageTableFlags::WRITABLE));
+    assert!(!heap.flags.contains(PageTableFlags::USER_ACCESSIBLE));
+    assert!(heap.flags.contains(PageTableFlags::NO_EXECUTE));
+}
+
+#[test_case]
+fn nothing_is_writable_and_executable() {
+    for range in mapped_ranges() {
+        assert!(
+            !range.flags.contains(PageTableFlags::WRITABLE)
+                || range.flags.contains(PageTableFlags::NO_EXECUTE),
+            "{} is writable and executable",
+            range
+        );
+    }
+}
+
+// immutable, so the compiler puts it with the read-only data
+static READ_ONLY_DATA: [u8; 4] = [1, 2, 3, 4];
+
+#[test_case]
+fn kernel_code_and_read_only_data_are_read_only() {
+    let code = VirtAddr::from_ptr(mapped_ranges as *const ());
+    let data = VirtAddr::from_ptr(&READ_ONLY_DATA);
+    for address in [code, data] {
+        let range = mapped_ranges()
+            .into_iter()
+            .find(|range| range.contains(address))
+            .expect("kernel image is not mapped");
+        assert!(!range.flags.contains(PageTableFlags::WRITABLE), "{}", range);
+    }
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use feebos::{
+    halt_loop,
+    kernel::k,
+    mmio::{map_mmio, Mmio},
+};
+use x86_64::PhysAddr;
+
+// qemu always has a local apic here
+const LOCAL_APIC: u64 = 0xFEE0_0000;
+const LOCAL_APIC_VERSION: usize = 0x30;
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+#[test_case]
+fn keeps_page_offset() {
+    let address = map_mmio(PhysAddr::new(LOCAL_APIC + 0x123), 8).unwrap();
+    assert_eq!(address.as_u64() % 4096, 0x123);
+}
+
+#[test_case]
+fn read_local_apic_version() {
+    let apic = Mmio::new(PhysAddr::new(LOCAL_APIC), 4096).unwrap();
+    let version = apic.read::<u32>(LOCAL_APIC_VERSION) & 0xFF;
+
+    // integrated apics report a version between 0x10 and 0x15
+    assert!((0x10..=0x15).contains(&version));
+}
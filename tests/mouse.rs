@@ -0,0 +1,115 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use feebos::{
+    halt_loop,
+    kernel::k,
+    mouse::{self, Buttons, MouseEvent, PacketParser},
+};
+
+// bit 3 of the first byte is always set
+const FLAGS: u8 = 1 << 3;
+const LEFT: u8 = 1 << 0;
+const MIDDLE: u8 = 1 << 2;
+const X_NEGATIVE: u8 = 1 << 4;
+const Y_NEGATIVE: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+fn parse(parser: &mut PacketParser, bytes: &[u8]) -> Option<MouseEvent> {
+    let (last, rest) = bytes.split_last().unwrap();
+    for &byte in rest {
+        assert_eq!(parser.push(byte), None);
+    }
+    parser.push(*last)
+}
+
+#[test_case]
+fn qemu_has_a_mouse() {
+    assert!(mouse::is_present());
+}
+
+#[test_case]
+fn parses_three_byte_packets() {
+    let mut parser = PacketParser::new(false);
+
+    let event = parse(&mut parser, &[FLAGS | LEFT, 5, 3]).unwrap();
+    assert_eq!(event.dx, 5);
+    // the mouse counts up as up, the screen counts down
+    assert_eq!(event.dy, -3);
+    assert_eq!(event.scroll, 0);
+    assert_eq!(
+        event.buttons,
+        Buttons {
+            left: true,
+            right: false,
+            middle: false
+        }
+    );
+
+    let event = parse(&mut parser, &[FLAGS | X_NEGATIVE | Y_NEGATIVE, 0xFB, 0xFF]).unwrap();
+    assert_eq!((event.dx, event.dy), (-5, 1));
+    assert_eq!(event.buttons, Buttons::default());
+}
+
+#[test_case]
+fn parses_scroll_wheel_packets() {
+    let mut parser = PacketParser::new(true);
+    assert_eq!(parser.packet_size(), 4);
+
+    let event = parse(&mut parser, &[FLAGS | MIDDLE, 0, 0, 0xFF]).unwrap();
+    assert_eq!(event.scroll, -1);
+    assert!(event.buttons.middle);
+
+    let event = parse(&mut parser, &[FLAGS, 0, 0, 1]).unwrap();
+    assert_eq!(event.scroll, 1);
+}
+
+#[test_case]
+fn drops_overflowed_movement() {
+    let mut parser = PacketParser::new(false);
+    let event = parse(&mut parser, &[FLAGS | X_OVERFLOW | LEFT, 0xFF, 0xFF]).unwrap();
+    assert_eq!((event.dx, event.dy), (0, 0));
+    assert!(event.buttons.left);
+}
+
+#[test_case]
+fn resynchronises_on_bad_first_byte() {
+    let mut parser = PacketParser::new(false);
+    // a stray byte without bit 3 set can't start a packet
+    assert_eq!(parser.push(0x01), None);
+    let event = parse(&mut parser, &[FLAGS, 7, 0]).unwrap();
+    assert_eq!(event.dx, 7);
+}
+
+#[test_case]
+fn injected_packets_are_queued() {
+    assert_eq!(mouse::try_read_event(), None);
+
+    let packet = [FLAGS | LEFT, 2, 0, 0];
+    for &byte in &packet[..mouse::packet_size()] {
+        mouse::inject_byte(byte);
+    }
+
+    let event = mouse::try_read_event().unwrap();
+    assert_eq!(event.dx, 2);
+    assert!(event.buttons.left);
+    assert_eq!(mouse::try_read_event(), None);
+}
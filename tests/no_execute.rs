@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::{
+    mem,
+    panic::PanicInfo,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use feebos::{exit_qemu, kernel::k, serial_print, serial_println, QemuExitCode};
+use lazy_static::lazy_static;
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+};
+
+entry_point!(test_entry_point);
+
+// where we jumped to, so the page fault can be checked against it
+static TARGET: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    assert!(error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH));
+    assert_eq!(Cr2::read().as_u64(), TARGET.load(Ordering::SeqCst));
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+}
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    // the test idt has no irq handlers, so a timer tick would triple fault
+    x86_64::instructions::interrupts::disable();
+    TEST_IDT.load();
+    serial_print!("{:.<76}", "no_execute::jump_into_heap");
+    jump_into_heap();
+    serial_println!("[heap code was executed]");
+    exit_qemu(QemuExitCode::Failed);
+}
+
+fn jump_into_heap() {
+    // a single `ret`, which would return straight back here if the heap were executable
+    let code = Box::new(0xc3u8);
+    let target = &*code as *const u8;
+    TARGET.store(target as u64, Ordering::SeqCst);
+
+    let function: extern "C" fn() = unsafe { mem::transmute(target) };
+    function();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use bootloader::{entry_point, BootInfo};
+use feebos::{
+    exit_qemu,
+    kernel::{k, KERNEL},
+    serial_print, serial_println,
+    serial_writer::SERIAL,
+    text_buffer::SHELL,
+    QemuExitCode,
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+const MESSAGE: &str = "panicking with the kernel locked";
+
+static MESSAGE_SENT: AtomicBool = AtomicBool::new(false);
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    serial_print!("{:.<76}", "panic_while_locked::reports_panic");
+    feebos::panic::set_finish(check);
+    without_interrupts(|| SERIAL.lock().set_tee(Some(watch_for_message)));
+
+    let _kernel = k();
+    let _shell = SHELL.lock();
+    panic!("{}", MESSAGE);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::panic::handle(info)
+}
+
+fn watch_for_message(written: &str) {
+    if written.contains(MESSAGE) {
+        MESSAGE_SENT.store(true, Ordering::SeqCst);
+    }
+}
+
+// where the kernel's panic handler ends up, instead of halting
+fn check() -> ! {
+    SERIAL.lock().set_tee(None);
+    if !MESSAGE_SENT.load(Ordering::SeqCst) {
+        serial_println!("[failed]");
+        serial_println!("the panic message never reached serial");
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    // taking the locks again would hang if they'd been left locked
+    if KERNEL.is_locked() || SHELL.is_locked() {
+        serial_println!("[failed]");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    drop(k());
+    drop(SHELL.lock());
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+}
@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use feebos::{halt_loop, kernel::k, rtc};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+// the test runner starts qemu with `-rtc base=2021-06-15T12:34:56`
+#[test_case]
+fn reads_pinned_date() {
+    let now = rtc::wall_clock();
+    assert_eq!((now.year, now.month, now.day), (2021, 6, 15));
+
+    // the clock keeps running from the pinned time, but tests time out after 10 seconds
+    let seconds =
+        |hour: u8, minute: u8, second: u8| hour as u32 * 3600 + minute as u32 * 60 + second as u32;
+    let elapsed = seconds(now.hour, now.minute, now.second) - seconds(12, 34, 56);
+    assert!(elapsed <= 10, "the clock is at {}", now);
+}
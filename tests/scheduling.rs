@@ -0,0 +1,134 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use feebos::{
+    halt_loop,
+    kernel::k,
+    keyboard,
+    scheduler::{self, Priority, WaitQueue, TIME_SLICE},
+    timer,
+};
+use pc_keyboard::DecodedKey;
+
+// scan code set 1
+const A_DOWN: u8 = 0x1E;
+const A_UP: u8 = 0x9E;
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+type Log = Arc<spin::Mutex<Vec<u8>>>;
+
+fn spin_for(duration: Duration) {
+    let deadline = timer::ticks() + timer::duration_to_ticks(duration);
+    while timer::ticks() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+#[test_case]
+fn higher_priority_runs_first() {
+    let log = Log::default();
+    let spawn = |priority, name| {
+        let log = log.clone();
+        scheduler::spawn_with_priority(priority, move || log.lock().push(name)).unwrap()
+    };
+
+    // the low priority thread can't run until we block, but the high one takes over straight away
+    let low = spawn(Priority::Low, b'l');
+    let high = spawn(Priority::High, b'h');
+    spin_for(TIME_SLICE);
+    assert_eq!(&log.lock()[..], b"h");
+
+    low.join();
+    high.join();
+    assert_eq!(&log.lock()[..], b"hl");
+}
+
+#[test_case]
+fn waking_thread_preempts_busy_one() {
+    let log = Log::default();
+    let busy = {
+        let log = log.clone();
+        scheduler::spawn(move || {
+            spin_for(TIME_SLICE * 5);
+            log.lock().push(b'n');
+        })
+        .unwrap()
+    };
+    let sleeper = {
+        let log = log.clone();
+        scheduler::spawn_with_priority(Priority::High, move || {
+            scheduler::sleep(Duration::from_millis(3));
+            log.lock().push(b'h');
+        })
+        .unwrap()
+    };
+
+    busy.join();
+    sleeper.join();
+    assert_eq!(&log.lock()[..], b"hn");
+}
+
+#[test_case]
+fn sleep_blocks_for_the_duration() {
+    let start = timer::ticks();
+    scheduler::sleep(Duration::from_millis(20));
+    assert!(timer::ticks() - start >= 20);
+}
+
+#[test_case]
+fn wait_queue_wakes_blocked_thread() {
+    static QUEUE: WaitQueue = WaitQueue::new();
+    static READY: AtomicBool = AtomicBool::new(false);
+
+    let waiter = scheduler::spawn(|| QUEUE.wait_until(|| READY.load(Ordering::SeqCst))).unwrap();
+
+    // the waiter blocks rather than spinning, so only the idle thread runs while we sleep
+    scheduler::sleep(Duration::from_millis(20));
+    assert!(!waiter.is_finished());
+
+    READY.store(true, Ordering::SeqCst);
+    assert!(QUEUE.wake_one());
+    waiter.join();
+    assert!(!QUEUE.wake_one());
+}
+
+#[test_case]
+fn keyboard_wakes_reader() {
+    let key = Arc::new(spin::Mutex::new(None));
+    let reader = {
+        let key = key.clone();
+        scheduler::spawn(move || *key.lock() = Some(keyboard::read_key().key)).unwrap()
+    };
+
+    scheduler::sleep(Duration::from_millis(5));
+    assert!(!reader.is_finished());
+
+    keyboard::inject_scancode(A_DOWN);
+    keyboard::inject_scancode(A_UP);
+    reader.join();
+    assert_eq!(*key.lock(), Some(DecodedKey::Unicode('a')));
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use feebos::{acpi, halt_loop, kernel::k, smp};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+#[test_case]
+fn every_cpu_in_the_madt_comes_online() {
+    let processors = &acpi::madt().expect("no madt found").processors;
+    let enabled = processors
+        .iter()
+        .filter(|processor| processor.enabled)
+        .count();
+    assert_eq!(smp::cpus_online(), enabled);
+}
+
+#[test_case]
+fn qemu_has_four_cpus() {
+    // the test runner starts qemu with -smp 4
+    assert_eq!(smp::cpus_online(), 4);
+}
+
+#[test_case]
+fn boot_cpu_still_takes_interrupts() {
+    // the application processors are halted, so this only returns if the boot cpu's timer runs
+    for _ in 0..10 {
+        x86_64::instructions::hlt();
+    }
+}
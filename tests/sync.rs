@@ -0,0 +1,151 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use feebos::{
+    halt_loop,
+    kernel::k,
+    scheduler::{self, JoinHandle},
+    sync::{Condvar, IrqSpinlock, Mutex, RwLock, Semaphore},
+};
+use x86_64::instructions::interrupts;
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+fn spawn_many(count: usize, entry: impl Fn() + Send + Sync + 'static) -> Vec<JoinHandle> {
+    let entry = Arc::new(entry);
+    (0..count)
+        .map(|_| {
+            let entry = entry.clone();
+            scheduler::spawn(move || entry()).unwrap()
+        })
+        .collect()
+}
+
+fn join_all(threads: Vec<JoinHandle>) {
+    for thread in threads {
+        thread.join();
+    }
+}
+
+#[test_case]
+fn irq_spinlock_holds_off_interrupts() {
+    let lock = IrqSpinlock::new(0);
+    assert!(interrupts::are_enabled());
+    {
+        let mut value = lock.lock();
+        *value += 1;
+        assert!(!interrupts::are_enabled());
+        assert!(lock.try_lock().is_none());
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*lock.lock(), 1);
+}
+
+#[test_case]
+fn mutex_excludes_other_threads() {
+    let mutex = Arc::new(Mutex::new(0));
+    let threads = {
+        let mutex = mutex.clone();
+        spawn_many(4, move || {
+            for _ in 0..10 {
+                let mut value = mutex.lock();
+                let before = *value;
+                // nobody else can get in while we're away
+                scheduler::yield_now();
+                *value = before + 1;
+            }
+        })
+    };
+
+    join_all(threads);
+    assert_eq!(*mutex.lock(), 40);
+    assert!(!mutex.is_locked());
+}
+
+#[test_case]
+fn semaphore_limits_concurrency() {
+    static SEMAPHORE: Semaphore = Semaphore::new(2);
+    static INSIDE: AtomicUsize = AtomicUsize::new(0);
+    static MOST_INSIDE: AtomicUsize = AtomicUsize::new(0);
+
+    let threads = spawn_many(5, || {
+        SEMAPHORE.acquire();
+        let inside = INSIDE.fetch_add(1, Ordering::SeqCst) + 1;
+        MOST_INSIDE.fetch_max(inside, Ordering::SeqCst);
+        scheduler::sleep(Duration::from_millis(5));
+        INSIDE.fetch_sub(1, Ordering::SeqCst);
+        SEMAPHORE.release();
+    });
+
+    join_all(threads);
+    assert_eq!(MOST_INSIDE.load(Ordering::SeqCst), 2);
+    assert_eq!(SEMAPHORE.available(), 2);
+}
+
+#[test_case]
+fn condvar_wakes_waiters() {
+    let state = Arc::new((Mutex::new(false), Condvar::new()));
+    let threads = {
+        let state = state.clone();
+        spawn_many(3, move || {
+            let (ready, condvar) = &*state;
+            let ready = condvar.wait_while(ready.lock(), |ready| !*ready);
+            assert!(*ready);
+        })
+    };
+
+    scheduler::sleep(Duration::from_millis(5));
+    assert!(threads.iter().all(|thread| !thread.is_finished()));
+
+    let (ready, condvar) = &*state;
+    *ready.lock() = true;
+    condvar.notify_all();
+    join_all(threads);
+}
+
+#[test_case]
+fn rwlock_shares_between_readers() {
+    let lock = Arc::new(RwLock::new(0));
+
+    let first = lock.read();
+    let second = lock.read();
+    assert_eq!(lock.readers(), Some(2));
+    assert!(lock.try_write().is_none());
+
+    let writer = {
+        let lock = lock.clone();
+        scheduler::spawn(move || *lock.write() += 1).unwrap()
+    };
+    scheduler::sleep(Duration::from_millis(5));
+    assert!(!writer.is_finished());
+    assert_eq!(*first + *second, 0);
+
+    drop(first);
+    drop(second);
+    writer.join();
+    assert_eq!(*lock.read(), 1);
+    assert_eq!(lock.readers(), Some(0));
+}
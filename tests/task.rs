@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::{cell::RefCell, panic::PanicInfo, time::Duration};
+use feebos::{
+    halt_loop,
+    kernel::k,
+    keyboard::{self, ScancodeStream},
+    task::{Executor, Stream, Task},
+    timer::{self, TimerError},
+};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+#[test_case]
+fn tasks_run_to_completion() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+    for i in 0..3 {
+        let log = log.clone();
+        executor.spawn(Task::new(async move { log.borrow_mut().push(i) }));
+    }
+
+    executor.run_until_idle();
+    assert_eq!(*log.borrow(), [0, 1, 2]);
+}
+
+#[test_case]
+fn tasks_wait_for_timers() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+    for (i, delay) in [30, 10, 20].into_iter().enumerate() {
+        let log = log.clone();
+        executor.spawn(Task::new(async move {
+            timer::delay(Duration::from_millis(delay)).await.unwrap();
+            log.borrow_mut().push(i);
+        }));
+    }
+
+    executor.run_until_idle();
+    assert_eq!(*log.borrow(), [1, 2, 0]);
+}
+
+#[test_case]
+fn delay_fails_without_a_free_timer() {
+    fn nothing() {}
+    let mut timers = Vec::new();
+    while let Ok(timer) = timer::after(Duration::from_secs(3600), nothing) {
+        timers.push(timer);
+    }
+
+    let result = Rc::new(RefCell::new(None));
+    let mut executor = Executor::new();
+    let log = result.clone();
+    executor.spawn(Task::new(async move {
+        *log.borrow_mut() = Some(timer::delay(Duration::from_millis(10)).await);
+    }));
+    executor.run_until_idle();
+
+    for timer in timers {
+        timer::cancel(timer);
+    }
+    assert_eq!(*result.borrow(), Some(Err(TimerError::TooManyTimers)));
+}
+
+#[test_case]
+fn scancode_stream_wakes_up() {
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+
+    let log = received.clone();
+    executor.spawn(Task::new(async move {
+        let mut scancodes = ScancodeStream::new();
+        while log.borrow().len() < 3 {
+            let scancode = scancodes.next().await.unwrap();
+            log.borrow_mut().push(scancode);
+        }
+    }));
+
+    // type while the stream is waiting, from a timer interrupt
+    fn type_keys() {
+        for scancode in [0x1E, 0x9E, 0x30] {
+            keyboard::inject_scancode(scancode);
+        }
+    }
+    timer::after(Duration::from_millis(10), type_keys).unwrap();
+
+    executor.run_until_idle();
+    assert_eq!(*received.borrow(), [0x1E, 0x9E, 0x30]);
+}
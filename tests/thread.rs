@@ -0,0 +1,146 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use feebos::{
+    halt_loop,
+    kernel::k,
+    scheduler::{self, TIME_SLICE},
+    serial_println, timer,
+};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+type Log = Arc<spin::Mutex<Vec<u8>>>;
+
+// burn through about `slices` time slices without ever yielding
+fn spin_for(slices: u64) {
+    let ticks = slices * TIME_SLICE.as_millis() as u64 * timer::TICK_FREQUENCY as u64 / 1000;
+    let deadline = timer::ticks() + ticks;
+    while timer::ticks() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+#[test_case]
+fn threads_run_until_joined() {
+    let ran = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let ran = ran.clone();
+        scheduler::spawn(move || ran.store(true, Ordering::SeqCst)).unwrap()
+    };
+    assert_ne!(thread.id(), scheduler::current());
+
+    thread.join();
+    assert!(ran.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn yielding_threads_take_turns() {
+    let log = Log::default();
+    let threads: Vec<_> = b"ab"
+        .iter()
+        .map(|&name| {
+            let log = log.clone();
+            scheduler::spawn(move || {
+                for _ in 0..3 {
+                    log.lock().push(name);
+                    scheduler::yield_now();
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join();
+    }
+    assert_eq!(&log.lock()[..], b"ababab");
+}
+
+#[test_case]
+fn busy_threads_are_preempted() {
+    let log = Log::default();
+    let threads: Vec<_> = b"abc"
+        .iter()
+        .map(|&name| {
+            let log = log.clone();
+            scheduler::spawn(move || {
+                for step in 0..5 {
+                    serial_println!("thread {} step {}", name as char, step);
+                    log.lock().push(name);
+                    spin_for(2);
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join();
+    }
+
+    // run back to back the log would only change hands twice
+    let log = log.lock();
+    let switches = log.windows(2).filter(|pair| pair[0] != pair[1]).count();
+    assert_eq!(log.len(), 15);
+    assert!(switches > 2, "threads didn't interleave: {:?}", &log[..]);
+}
+
+#[test_case]
+fn spinning_thread_does_not_starve_others() {
+    let flag = Arc::new(AtomicBool::new(false));
+    let waiter = {
+        let flag = flag.clone();
+        scheduler::spawn(move || {
+            // never yields, so only preemption lets the setter run
+            while !flag.load(Ordering::SeqCst) {
+                core::hint::spin_loop();
+            }
+        })
+        .unwrap()
+    };
+    let setter = scheduler::spawn(move || flag.store(true, Ordering::SeqCst)).unwrap();
+
+    waiter.join();
+    setter.join();
+}
+
+#[test_case]
+fn exit_finishes_early() {
+    let after_exit = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let after_exit = after_exit.clone();
+        scheduler::spawn(move || {
+            if !after_exit.load(Ordering::SeqCst) {
+                scheduler::exit();
+            }
+            after_exit.store(true, Ordering::SeqCst);
+        })
+        .unwrap()
+    };
+
+    thread.join();
+    assert!(!after_exit.load(Ordering::SeqCst));
+}
@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::{panic::PanicInfo, time::Duration};
+use feebos::{
+    halt_loop,
+    kernel::k,
+    time::{self, Instant},
+    timer,
+};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+#[test_case]
+fn tsc_is_calibrated() {
+    let frequency = time::tsc_frequency().unwrap();
+    // anything from a very slow emulator up to a very fast cpu
+    assert!((100_000_000..10_000_000_000).contains(&frequency));
+}
+
+#[test_case]
+fn instants_are_monotonic() {
+    let mut last = Instant::now();
+    for _ in 0..1000 {
+        let now = Instant::now();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn resolution_is_finer_than_ticks() {
+    let start = Instant::now();
+    let mut end = Instant::now();
+    while end == start {
+        end = Instant::now();
+    }
+    assert!(end - start < Duration::from_micros(100));
+}
+
+#[test_case]
+fn agrees_with_timer() {
+    let start = Instant::now();
+    timer::sleep(Duration::from_millis(100));
+    let elapsed = start.elapsed();
+
+    // the two clocks are calibrated separately, so allow them to disagree a little
+    assert!(
+        elapsed >= Duration::from_millis(95),
+        "slept for {:?}",
+        elapsed
+    );
+    assert!(
+        elapsed <= Duration::from_millis(110),
+        "slept for {:?}",
+        elapsed
+    );
+}
@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use feebos::{
+    halt_loop,
+    kernel::k,
+    timer::{self, TICK_FREQUENCY},
+};
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+#[test_case]
+fn ticks_advance() {
+    let ticks = timer::ticks();
+    x86_64::instructions::hlt();
+    assert!(timer::ticks() > ticks);
+}
+
+#[test_case]
+fn sleep_matches_ticks() {
+    let duration = Duration::from_millis(100);
+    let expected = TICK_FREQUENCY as u64 / 10;
+
+    let start = timer::ticks();
+    timer::sleep(duration);
+    let elapsed = timer::ticks() - start;
+
+    // never early, and not late by more than a tick or two
+    assert!(elapsed >= expected, "woke up after {} ticks", elapsed);
+    assert!(elapsed <= expected + 2, "woke up after {} ticks", elapsed);
+}
+
+#[test_case]
+fn uptime_follows_ticks() {
+    let before = timer::uptime();
+    timer::sleep(Duration::from_millis(10));
+    let after = timer::uptime();
+    assert!(after - before >= Duration::from_millis(10));
+    assert!(after - before <= Duration::from_millis(12));
+}
+
+static FIRED: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+static FIRED_COUNT: AtomicUsize = AtomicUsize::new(0);
+static PERIODIC_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn record(timer: usize) {
+    let position = FIRED_COUNT.fetch_add(1, Ordering::SeqCst);
+    FIRED[position].store(timer, Ordering::SeqCst);
+}
+
+fn first() {
+    record(1);
+}
+
+fn second() {
+    record(2);
+}
+
+fn third() {
+    record(3);
+}
+
+fn periodic() {
+    PERIODIC_CALLS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn timers_fire_in_deadline_order() {
+    timer::after(Duration::from_millis(30), third).unwrap();
+    timer::after(Duration::from_millis(10), first).unwrap();
+    timer::after(Duration::from_millis(20), second).unwrap();
+    let cancelled = timer::after(Duration::from_millis(15), third).unwrap();
+    assert!(timer::cancel(cancelled));
+
+    timer::sleep(Duration::from_millis(40));
+    assert_eq!(FIRED_COUNT.load(Ordering::SeqCst), 3);
+    for (position, timer) in FIRED.iter().enumerate() {
+        assert_eq!(timer.load(Ordering::SeqCst), position + 1);
+    }
+    assert!(!timer::cancel(cancelled));
+}
+
+#[test_case]
+fn periodic_timers_repeat_until_cancelled() {
+    let timer = timer::every(Duration::from_millis(5), periodic).unwrap();
+    timer::sleep(Duration::from_millis(52));
+    assert!(timer::cancel(timer));
+
+    let calls = PERIODIC_CALLS.load(Ordering::SeqCst);
+    assert!((9..=11).contains(&calls), "called {} times", calls);
+
+    timer::sleep(Duration::from_millis(20));
+    assert_eq!(PERIODIC_CALLS.load(Ordering::SeqCst), calls);
+}
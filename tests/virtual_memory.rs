@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(feebos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use feebos::{
+    halt_loop,
+    kernel::k,
+    vmm::{Region, VMM},
+};
+use x86_64::structures::paging::PageTableFlags;
+
+entry_point!(test_entry_point);
+
+fn test_entry_point(boot_info: &'static mut BootInfo) -> ! {
+    k().init(boot_info);
+    test_main();
+    halt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    feebos::test_panic_handler(info)
+}
+
+#[test_case]
+fn reservations_do_not_overlap() {
+    let first = VMM.reserve(Region::Stacks, 100).unwrap();
+    let second = VMM.reserve(Region::Stacks, 4096).unwrap();
+    let third = VMM.reserve(Region::Stacks, 1).unwrap();
+    assert!(first >= Region::Stacks.start());
+    assert_eq!(second, first + 4096u64);
+    assert_eq!(third, second + 4096u64);
+}
+
+#[test_case]
+fn regions_run_out() {
+    let size = (Region::Framebuffer.end() - Region::Framebuffer.start()) as usize;
+    assert!(VMM.reserve(Region::Framebuffer, size + 1).is_none());
+    assert!(VMM.reserve(Region::Framebuffer, size).is_some());
+    assert!(VMM.reserve(Region::Framebuffer, 1).is_none());
+}
+
+#[test_case]
+fn map_and_unmap() {
+    let size = 4 * 4096;
+    let start = VMM.reserve(Region::Stacks, size).unwrap();
+    let free_before = VMM.with_frame_allocator(|frame_allocator| frame_allocator.free_frames());
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    VMM.map(start, size, flags).unwrap();
+    let memory = unsafe { core::slice::from_raw_parts_mut(start.as_mut_ptr::<u8>(), size) };
+    memory.fill(0x42);
+    assert!(memory.iter().all(|&byte| byte == 0x42));
+
+    // drop write access, then unmap. a second unmap finds nothing there
+    VMM.protect(start, size, PageTableFlags::PRESENT).unwrap();
+    VMM.unmap(start, size).unwrap();
+    assert!(VMM.unmap(start, size).is_err());
+
+    // the pages themselves are back, though page tables made along the way are kept
+    let free_after = VMM.with_frame_allocator(|frame_allocator| frame_allocator.free_frames());
+    assert!(free_after + 3 >= free_before);
+}